@@ -10,9 +10,14 @@ AS $BODY$
     BEGIN
     	SELECT  current_setting('salesforce.' || TG_TABLE_NAME ||'_lock',true) INTO table_lock;
         IF table_lock IS NULL OR table_lock <> 'lock' THEN
-        	PERFORM pg_notify('salesforce_data', TG_TABLE_NAME || '::' || NEW.id);
+            IF TG_OP = 'DELETE' THEN
+                -- the row is gone, so the sfid has to travel with the notification
+                PERFORM pg_notify('salesforce_data', TG_TABLE_NAME || '::' || OLD.id || '::' || TG_OP || '::' || COALESCE(OLD.sfid, ''));
+            ELSE
+        	    PERFORM pg_notify('salesforce_data', TG_TABLE_NAME || '::' || NEW.id || '::' || TG_OP);
+            END IF;
         END IF;    
-        RETURN NEW;
+        RETURN COALESCE(NEW, OLD);
     END;
 
 $BODY$;
@@ -0,0 +1,12 @@
+-- per object choice between soft (_s_state = 'DELETED') and hard deletes
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS hard_delete boolean NOT NULL DEFAULT false;
+
+DO $BODY$
+    DECLARE obj record;
+    BEGIN
+        FOR obj IN SELECT db_name FROM config.objects LOOP
+            EXECUTE format('ALTER TABLE salesforce.%I ADD COLUMN IF NOT EXISTS _s_deleted timestamp', obj.db_name);
+        END LOOP;
+    END;
+$BODY$;
@@ -0,0 +1,21 @@
+-- rerun 02_function.sql first, then extend existing triggers to cover DELETE
+
+CREATE TABLE IF NOT EXISTS config.failed_deletes (
+    id SERIAL PRIMARY KEY,
+    object_name varchar(255),
+    record_id integer,
+    sfid varchar(18),
+    error text,
+    created timestamp DEFAULT now()
+);
+
+DO $BODY$
+    DECLARE obj record;
+    BEGIN
+        FOR obj IN SELECT db_name FROM config.objects LOOP
+            EXECUTE format('DROP TRIGGER IF EXISTS %I ON salesforce.%I', obj.db_name || '_notify', obj.db_name);
+            EXECUTE format('CREATE TRIGGER %I AFTER INSERT OR UPDATE OR DELETE ON salesforce.%I FOR EACH ROW EXECUTE PROCEDURE salesforce.notify_change()',
+                           obj.db_name || '_notify', obj.db_name);
+        END LOOP;
+    END;
+$BODY$;
@@ -0,0 +1,3 @@
+-- highest SystemModstamp stored locally, drives the incremental sync
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS watermark timestamp;
@@ -0,0 +1,3 @@
+-- storage of address and location fields: 'split' into component columns or one 'json' column
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS compound_mode varchar(10) NOT NULL DEFAULT 'split';
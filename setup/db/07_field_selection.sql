@@ -0,0 +1,3 @@
+-- include and exclude lists applied to the describe of each object, as json
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS field_selection text NOT NULL DEFAULT '{}';
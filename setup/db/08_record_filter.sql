@@ -0,0 +1,4 @@
+-- optional soql condition limiting the synced records, and what happens to rows that stop matching it
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS record_filter text;
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS unmatched_policy varchar(10) NOT NULL DEFAULT 'keep';
@@ -0,0 +1,4 @@
+-- direction of each object ('both', 'sf_to_db', 'db_to_sf') and per field overrides as json
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS sync_direction varchar(10) NOT NULL DEFAULT 'both';
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS field_directions text NOT NULL DEFAULT '{}';
@@ -0,0 +1,42 @@
+-- conflict detection: SystemModstamp seen at the last pull and time of the last local change per row
+-- parked conflicts keep both versions, set _s_state to 'RESOLVED' after fixing a row to push it as is
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS conflict_policy varchar(20) NOT NULL DEFAULT 'salesforce_wins';
+
+CREATE TABLE IF NOT EXISTS config.conflicts (
+    id SERIAL PRIMARY KEY,
+    object_name varchar(255),
+    record_id integer,
+    sfid varchar(18),
+    db_version jsonb,
+    sf_version jsonb,
+    created timestamp DEFAULT now()
+);
+
+CREATE OR REPLACE FUNCTION salesforce.mark_modified()
+    RETURNS trigger
+    LANGUAGE 'plpgsql'
+AS $BODY$
+    DECLARE table_lock varchar;
+    BEGIN
+        SELECT current_setting('salesforce.' || TG_TABLE_NAME || '_lock', true) INTO table_lock;
+        IF table_lock IS NULL OR table_lock <> 'lock' THEN
+            -- compared with SystemModstamp, which is utc
+            NEW._s_modified = timezone('utc', now());
+        END IF;
+        RETURN NEW;
+    END;
+$BODY$;
+
+DO $BODY$
+    DECLARE obj record;
+    BEGIN
+        FOR obj IN SELECT db_name FROM config.objects LOOP
+            EXECUTE format('ALTER TABLE salesforce.%I ADD COLUMN IF NOT EXISTS _s_sf_modstamp timestamp', obj.db_name);
+            EXECUTE format('ALTER TABLE salesforce.%I ADD COLUMN IF NOT EXISTS _s_modified timestamp', obj.db_name);
+            EXECUTE format('DROP TRIGGER IF EXISTS %I ON salesforce.%I', obj.db_name || '_modified', obj.db_name);
+            EXECUTE format('CREATE TRIGGER %I BEFORE INSERT OR UPDATE ON salesforce.%I FOR EACH ROW EXECUTE PROCEDURE salesforce.mark_modified()',
+                           obj.db_name || '_modified', obj.db_name);
+        END LOOP;
+    END;
+$BODY$;
@@ -0,0 +1,38 @@
+-- durable change capture, the trigger writes every change to the outbox and NOTIFY only wakes up the executer
+
+CREATE TABLE IF NOT EXISTS config.outbox (
+    id BIGSERIAL PRIMARY KEY,
+    table_name varchar(255) NOT NULL,
+    record_id integer NOT NULL,
+    sfid varchar(18),
+    op varchar(10) NOT NULL,
+    txid bigint NOT NULL,
+    created timestamp DEFAULT now(),
+    claimed_at timestamp,
+    done timestamp
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON config.outbox (id) WHERE done IS NULL;
+
+CREATE OR REPLACE FUNCTION salesforce.notify_change()
+    RETURNS trigger
+    LANGUAGE 'plpgsql'
+AS $BODY$
+    DECLARE table_lock varchar;
+    BEGIN
+        SELECT  current_setting('salesforce.' || TG_TABLE_NAME ||'_lock',true) INTO table_lock;
+        IF table_lock IS NULL OR table_lock <> 'lock' THEN
+            IF TG_OP = 'DELETE' THEN
+                -- the row is gone, so the sfid has to be kept with the entry
+                INSERT INTO config.outbox (table_name, record_id, sfid, op, txid)
+                    VALUES (TG_TABLE_NAME, OLD.id, OLD.sfid, TG_OP, txid_current());
+            ELSE
+                INSERT INTO config.outbox (table_name, record_id, sfid, op, txid)
+                    VALUES (TG_TABLE_NAME, NEW.id, NEW.sfid, TG_OP, txid_current());
+            END IF;
+            PERFORM pg_notify('salesforce_data', TG_TABLE_NAME);
+        END IF;
+        RETURN COALESCE(NEW, OLD);
+    END;
+
+$BODY$;
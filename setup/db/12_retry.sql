@@ -0,0 +1,22 @@
+-- retry counter and schedule of failed pushes, rows that run out of attempts end up in the dead letters
+
+CREATE TABLE IF NOT EXISTS config.dead_letters (
+    id SERIAL PRIMARY KEY,
+    object_name varchar(255),
+    record_id integer,
+    sfid varchar(18),
+    error text,
+    attempts integer,
+    data jsonb,
+    created timestamp DEFAULT now()
+);
+
+DO $BODY$
+    DECLARE obj record;
+    BEGIN
+        FOR obj IN SELECT db_name FROM config.objects LOOP
+            EXECUTE format('ALTER TABLE salesforce.%I ADD COLUMN IF NOT EXISTS _s_attempts integer DEFAULT 0', obj.db_name);
+            EXECUTE format('ALTER TABLE salesforce.%I ADD COLUMN IF NOT EXISTS _s_retry_at timestamp', obj.db_name);
+        END LOOP;
+    END;
+$BODY$;
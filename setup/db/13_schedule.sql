@@ -0,0 +1,3 @@
+-- minutes between two pulls of each object, set from the declared objects in the config file
+
+ALTER TABLE config.objects ADD COLUMN IF NOT EXISTS schedule_minutes integer NOT NULL DEFAULT 1;
@@ -0,0 +1,5 @@
+-- last_sync_time is read as utc, values written in the local time of a server east of utc lie in the future
+-- and are moved back by the offset of the server
+
+UPDATE config.objects SET last_sync_time = last_sync_time - (now()::timestamp - timezone('utc', now()))
+    WHERE last_sync_time > timezone('utc', now());
@@ -0,0 +1,5 @@
+-- failed deletes are retried like failed pushes, entries out of attempts are listed with the dead letters
+
+ALTER TABLE config.failed_deletes ADD COLUMN IF NOT EXISTS attempts integer NOT NULL DEFAULT 0;
+ALTER TABLE config.failed_deletes ADD COLUMN IF NOT EXISTS retry_at timestamp;
+ALTER TABLE config.failed_deletes ADD COLUMN IF NOT EXISTS state varchar(20) NOT NULL DEFAULT 'FAILED';
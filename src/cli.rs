@@ -0,0 +1,314 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+use config::Config;
+use db::Db;
+use salesforce::Salesforce;
+use sync::Sync;
+use sync::setup::Setup;
+use sync::plan::PlanItem;
+use sync::shutdown;
+use logging::{self, Event};
+use metrics;
+use sync::executer::Executer;
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+const SIGNAL_POLL_MS: u64 = 500;
+const CONFIG_PATH: &str = "config/config.json";
+
+lazy_static! {
+    //read once the arguments are parsed, help and usage errors work without a config file
+    static ref CONFIG: Result<Config, String> = Config::new(CONFIG_PATH);
+}
+
+fn build_app<'a, 'b>() -> App<'a, 'b> {
+    let object_arg = Arg::with_name("object").required(true).help("Salesforce object name, e.g. Account");
+    App::new("rust-crm-sync")
+        .about("Two-way sync between Salesforce and Postgres")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("json").long("json").global(true).help("Prints machine readable output"))
+        .subcommand(SubCommand::with_name("interactive").about("Starts the interactive menu"))
+        .subcommand(SubCommand::with_name("objects")
+            .about("Manages synchronized objects")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list").about("Lists objects available in Salesforce"))
+            .subcommand(SubCommand::with_name("add")
+                .about("Creates the table of an object and loads its records")
+                .arg(object_arg.clone())
+                .arg(Arg::with_name("no-db-sync").long("no-db-sync").help("Does not push local changes")))
+            .subcommand(SubCommand::with_name("remove")
+                .about("Drops the table of an object")
+                .arg(object_arg.clone()))
+            .subcommand(SubCommand::with_name("status").about("Shows synchronized objects"))
+            .subcommand(SubCommand::with_name("plan").about("Shows the changes needed to match the declared objects"))
+            .subcommand(SubCommand::with_name("apply")
+                .about("Creates, updates and removes objects to match the declared objects")
+                .arg(Arg::with_name("allow-remove").long("allow-remove").help("Drops objects that are no longer declared"))))
+        .subcommand(SubCommand::with_name("sync")
+            .about("Runs the synchronization")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("run")
+                .about("Runs the synchronization in the foreground")
+                .arg(Arg::with_name("once").long("once").help("Stops after a single cycle")))
+            .subcommand(SubCommand::with_name("daemon").about("Runs the synchronization until SIGTERM or SIGINT")))
+        .subcommand(SubCommand::with_name("limits").about("Shows the api usage of the org and the budget of the sync"))
+        .subcommand(SubCommand::with_name("errors")
+            .about("Manages rows that could not be pushed")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+                .about("Lists dead lettered rows")
+                .arg(Arg::with_name("object").long("object").takes_value(true).help("Only rows of this object")))
+            .subcommand(SubCommand::with_name("requeue")
+                .about("Retries dead lettered rows")
+                .arg(object_arg.clone())
+                .arg(Arg::with_name("id").help("Only this row")))
+            .subcommand(SubCommand::with_name("discard")
+                .about("Drops the local changes of dead lettered rows")
+                .arg(object_arg.clone())
+                .arg(Arg::with_name("id").help("Only this row"))))
+}
+
+//runs the command line and returns the exit code
+pub fn run() -> i32 {
+    let matches = match build_app().get_matches_safe() {
+        Ok(matches) => matches,
+        Err(err) => {
+            if err.use_stderr() {
+                eprintln!("{}", err.message);
+                return EXIT_USAGE;
+            }
+            println!("{}", err.message);
+            return EXIT_OK;
+        }
+    };
+    let json = matches.is_present("json");
+    let config: &'static Config = match *CONFIG {
+        Ok(ref config) => config,
+        Err(ref err) => {
+            print_error(err, json);
+            return EXIT_ERROR;
+        }
+    };
+    if let Err(err) = logging::init(&config.logging, json) {
+        print_error(&err, json);
+        return EXIT_ERROR;
+    }
+    let result = match matches.subcommand() {
+        ("interactive", _) => {
+            reconcile_on_start(config)
+                .and_then(|_| Sync::new(config))
+                .map(|mut sync| {
+                    sync.run();
+                    Value::Null
+                })
+        },
+        ("objects", Some(sub)) => objects(config, sub),
+        ("sync", Some(sub)) => reconcile_on_start(config).and_then(|_| sync(config, sub)),
+        ("errors", Some(sub)) => errors(config, sub),
+        ("limits", _) => limits(config),
+        _ => Err("Unknown command".to_owned()),
+    };
+    logging::flush();
+    match result {
+        Ok(Value::Null) => EXIT_OK,
+        Ok(output) => {
+            print_output(&output, json);
+            EXIT_OK
+        },
+        Err(err) => {
+            print_error(&err, json);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn print_error(err: &str, json: bool) {
+    if json {
+        println!("{}", json!({"error": err}));
+    } else {
+        eprintln!("Error: {}", err);
+    }
+}
+
+fn build_setup(config: &'static Config) -> Result<Setup, String> {
+    let db = Arc::new(Db::new(&config.db)?);
+    let salesforce = Arc::new(Salesforce::new(&config.salesforce).map_err(|err| err.to_string())?);
+    Ok(Setup::new(db, salesforce, &config.sync))
+}
+
+fn objects(config: &'static Config, matches: &ArgMatches) -> Result<Value, String> {
+    let setup = build_setup(config)?;
+    match matches.subcommand() {
+        ("list", _) => {
+            let mut objects = vec!();
+            setup.list_salesforce_objects(|obj| {
+                objects.push(json!({"name": obj.1, "createable": obj.2}));
+            })?;
+            Ok(Value::Array(objects))
+        },
+        ("add", Some(sub)) => {
+            let (name, row_count) = setup.setup_object(sub.value_of("object").unwrap(), !sub.is_present("no-db-sync"))?;
+            Ok(json!({"name": name, "rows": row_count}))
+        },
+        ("remove", Some(sub)) => {
+            let name = setup.remove_object(sub.value_of("object").unwrap())?;
+            Ok(json!({"name": name, "removed": true}))
+        },
+        ("status", _) => {
+            let objects = setup.get_db_objects()?
+                .iter()
+                .map(|obj| json!({
+                    "name": obj.name,
+                    "rows": obj.count,
+                    "watermark": obj.watermark.map(|watermark| watermark.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                    "direction": obj.direction.as_str(),
+                    "conflict_policy": obj.conflict_policy.as_str(),
+                }))
+                .collect();
+            Ok(Value::Array(objects))
+        },
+        ("plan", _) => Ok(plan_output(&setup.plan_objects()?)),
+        ("apply", Some(sub)) => {
+            let allow_remove = sub.is_present("allow-remove") || config.sync.allow_remove;
+            Ok(plan_output(&setup.apply_objects(allow_remove)?))
+        },
+        _ => Err("Unknown objects command".to_owned()),
+    }
+}
+
+fn plan_output(items: &Vec<PlanItem>) -> Value {
+    let items = items.iter()
+        .map(|item| json!({
+            "object": item.object,
+            "action": item.action.as_str(),
+            "changes": item.changes.join(", "),
+        }))
+        .collect();
+    Value::Array(items)
+}
+
+//brings the synced objects in line with the config file before the sync starts
+fn reconcile_on_start(config: &'static Config) -> Result<(), String> {
+    if !config.sync.reconcile_on_start {
+        return Ok(());
+    }
+    let setup = build_setup(config)?;
+    for item in setup.apply_objects(config.sync.allow_remove)? {
+        Event::info(&format!("Applied {}", item.action.as_str())).with_object(&item.object).emit();
+    }
+    Ok(())
+}
+
+fn sync(config: &'static Config, matches: &ArgMatches) -> Result<Value, String> {
+    let db = Arc::new(Db::new(&config.db)?);
+    let salesforce = Arc::new(Salesforce::new(&config.salesforce).map_err(|err| err.to_string())?);
+    let mut executer = Executer::new(db, salesforce, &config.sync);
+    let once = match matches.subcommand() {
+        ("run", Some(sub)) => sub.is_present("once"),
+        ("daemon", _) => false,
+        _ => return Err("Unknown sync command".to_owned()),
+    };
+    if once {
+        executer.run_once(logging::sender());
+        return Ok(Value::Null);
+    }
+    if let Some(ref metrics_config) = config.metrics {
+        metrics::server::start(&metrics_config.address)?;
+    }
+    shutdown::install_handlers();
+    executer.start_sync();
+    while !shutdown::requested() {
+        sleep(Duration::from_millis(SIGNAL_POLL_MS));
+    }
+    Event::info("Shutting down, waiting for the running cycle").emit();
+    let stopped = executer.stop_sync();
+    if !stopped {
+        return Err(format!("Sync did not stop within {} seconds", config.sync.shutdown_timeout_secs));
+    }
+    Ok(Value::Null)
+}
+
+fn limits(config: &'static Config) -> Result<Value, String> {
+    let salesforce = Salesforce::new(&config.salesforce).map_err(|err| err.to_string())?;
+    let limits = salesforce.get_limits().map_err(|err| err.to_string())?;
+    let usage = salesforce.get_api_usage().ok_or("No api usage reported".to_owned())?;
+    Ok(json!({
+        "used": usage.used,
+        "max": usage.max,
+        "share": usage.share(),
+        "budget_share": config.salesforce.api_budget.as_ref().map(|budget| budget.max_share),
+        "throttle": salesforce.get_throttle().as_str(),
+        "bulk_remaining": limits["DailyBulkApiRequests"]["Remaining"],
+        "bulk_max": limits["DailyBulkApiRequests"]["Max"],
+    }))
+}
+
+fn errors(config: &'static Config, matches: &ArgMatches) -> Result<Value, String> {
+    //dead letters are kept in postgres, no salesforce login needed
+    let db = Db::new(&config.db)?;
+    match matches.subcommand() {
+        ("list", Some(sub)) => {
+            let rows = db.list_dead_letters(sub.value_of("object"))?
+                .iter()
+                .map(|row| json!({
+                    "id": row.id,
+                    "object": row.object_name,
+                    "record_id": row.record_id,
+                    "sfid": row.sfid,
+                    "error": row.error,
+                    "attempts": row.attempts,
+                    "op": row.op,
+                    "created": row.created.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                }))
+                .collect();
+            Ok(Value::Array(rows))
+        },
+        ("requeue", Some(sub)) => {
+            let count = db.requeue_dead_letters(sub.value_of("object").unwrap(), parse_id(sub)?)?;
+            Ok(json!({"requeued": count}))
+        },
+        ("discard", Some(sub)) => {
+            let count = db.discard_dead_letters(sub.value_of("object").unwrap(), parse_id(sub)?)?;
+            Ok(json!({"discarded": count}))
+        },
+        _ => Err("Unknown errors command".to_owned()),
+    }
+}
+
+fn parse_id(matches: &ArgMatches) -> Result<Option<i32>, String> {
+    match matches.value_of("id") {
+        Some(id) => id.parse::<i32>().map(Some).map_err(|_| format!("Invalid id: {}", id)),
+        None => Ok(None),
+    }
+}
+
+//lists are printed one row per line, objects as key: value pairs
+fn print_output(output: &Value, json: bool) {
+    if json {
+        println!("{}", output);
+        return;
+    }
+    let rows = match *output {
+        Value::Array(ref rows) => rows.clone(),
+        ref value => vec!(value.clone()),
+    };
+    for row in rows {
+        match row {
+            Value::Object(map) => {
+                let columns: Vec<String> = map.iter()
+                    .map(|(key, value)| match *value {
+                        Value::String(ref val) => format!("{}: {}", key, val),
+                        ref val => format!("{}: {}", key, val),
+                    })
+                    .collect();
+                println!("{}", columns.join("\t"));
+            },
+            value => println!("{}", value),
+        }
+    }
+}
@@ -1,16 +1,136 @@
 use serde_json;
 use std::io::prelude::*;
 use std::fs::File;
+use std::collections::HashMap;
+use std::cmp::max;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SalesforceConfig {
     pub uri: String,
     pub client_id: String,
+    #[serde(default)]
     pub client_secret: String,
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    #[serde(default)]
     pub sec_token: String,
-    pub api_version: String
+    pub api_version: String,
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    #[serde(default = "default_session_timeout")]
+    pub session_timeout: u64,
+    #[serde(default)]
+    pub bulk_threshold: Option<u64>,
+    #[serde(default = "default_bulk_page_size")]
+    pub bulk_page_size: u64,
+    //without a budget the api usage is only tracked
+    #[serde(default)]
+    pub api_budget: Option<ApiBudget>,
+    #[serde(default)]
+    pub http_retry: HttpRetryConfig,
+}
+
+//retries of requests that failed with a transient error
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HttpRetryConfig {
+    #[serde(default = "default_http_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_http_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "default_http_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for HttpRetryConfig {
+    fn default() -> HttpRetryConfig {
+        HttpRetryConfig {
+            max_attempts: default_http_max_attempts(),
+            base_delay_ms: default_http_base_delay_ms(),
+            max_delay_ms: default_http_max_delay_ms(),
+        }
+    }
+}
+
+impl HttpRetryConfig {
+    //upper bound of the delay before the given retry, the actual delay is picked at random below it
+    pub fn max_delay(&self, attempt: u32) -> u64 {
+        self.base_delay_ms.saturating_mul(2u64.pow(attempt.min(30))).min(self.max_delay_ms)
+    }
+}
+
+fn default_http_max_attempts() -> u32 {
+    4
+}
+
+fn default_http_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_http_max_delay_ms() -> u64 {
+    30000
+}
+
+//share of the org's daily api requests the sync may use
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiBudget {
+    pub max_share: f64,
+    //share of the budget after which the executers slow down
+    #[serde(default = "default_slow_down_at")]
+    pub slow_down_at: f64,
+    //multiplies the sync timeout while slowed down
+    #[serde(default = "default_slow_down_factor")]
+    pub slow_down_factor: u64,
+}
+
+fn default_slow_down_at() -> f64 {
+    0.8
+}
+
+fn default_slow_down_factor() -> u64 {
+    4
+}
+
+fn default_session_timeout() -> u64 {
+    //salesforce default session timeout of 2 hours
+    7200
+}
+
+fn default_bulk_page_size() -> u64 {
+    50000
+}
+
+impl SalesforceConfig {
+    //falls back to the password grant configured at the top level
+    pub fn get_auth(&self) -> AuthConfig {
+        match self.auth {
+            Some(ref auth) => auth.clone(),
+            None => AuthConfig::Password {
+                username: self.username.clone(),
+                password: self.password.clone(),
+                sec_token: self.sec_token.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "flow", rename_all = "snake_case")]
+pub enum AuthConfig {
+    Password {
+        username: String,
+        password: String,
+        #[serde(default)]
+        sec_token: String,
+    },
+    JwtBearer {
+        username: String,
+        private_key_file: String,
+        #[serde(default)]
+        audience: Option<String>,
+    },
+    ClientCredentials,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -21,6 +141,399 @@ pub struct DbConfig {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SyncConfig {
     pub timeout: u64,
+    #[serde(default = "default_overlap_secs")]
+    pub overlap_secs: i64,
+    #[serde(default = "default_schema_check_interval")]
+    pub schema_check_interval: u64,
+    #[serde(default)]
+    pub compound_fields: CompoundMode,
+    //declared objects keyed by object name, reconciled with config.objects by plan and apply
+    #[serde(default)]
+    pub objects: HashMap<String, ObjectSettings>,
+    //applies the declared objects before the sync starts
+    #[serde(default)]
+    pub reconcile_on_start: bool,
+    //lets apply drop synced objects that are no longer declared
+    #[serde(default)]
+    pub allow_remove: bool,
+    //drops the column of a field that is gone from the describe, otherwise the column is kept and no longer synced
+    #[serde(default)]
+    pub drop_removed_columns: bool,
+    #[serde(default = "default_outbox_batch_size")]
+    pub outbox_batch_size: i64,
+    //claimed outbox entries are handed out again once the lease expires
+    #[serde(default = "default_outbox_lease_secs")]
+    pub outbox_lease_secs: i64,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    //how long stopping waits for the running cycles to finish
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+}
+
+//retries of failed pushes, rows are moved to config.dead_letters after max_attempts
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i32,
+    #[serde(default = "default_base_delay_secs")]
+    pub base_delay_secs: i64,
+    #[serde(default = "default_max_delay_secs")]
+    pub max_delay_secs: i64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig {
+            max_attempts: default_max_attempts(),
+            base_delay_secs: default_base_delay_secs(),
+            max_delay_secs: default_max_delay_secs(),
+        }
+    }
+}
+
+impl RetryConfig {
+    //exponential backoff, doubled with every failed attempt
+    pub fn backoff_secs(&self, attempts: i32) -> i64 {
+        let exponent = max(attempts - 1, 0).min(30) as u32;
+        self.base_delay_secs.saturating_mul(2i64.pow(exponent)).min(self.max_delay_secs)
+    }
+}
+
+impl SyncConfig {
+    //object names are case insensitive in salesforce
+    pub fn find_object_settings(&self, object_name: &str) -> Option<&ObjectSettings> {
+        self.objects.iter()
+            .find(|&(name, _)| name.eq_ignore_ascii_case(object_name))
+            .map(|(_, settings)| settings)
+    }
+
+    pub fn get_object_settings(&self, object_name: &str) -> ObjectSettings {
+        self.find_object_settings(object_name).cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ObjectSettings {
+    #[serde(default)]
+    pub fields: FieldSelection,
+    //soql condition without the WHERE keyword
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub unmatched: UnmatchedPolicy,
+    #[serde(default)]
+    pub direction: SyncDirection,
+    //overrides of the object direction, keyed by field name
+    #[serde(default)]
+    pub field_directions: HashMap<String, SyncDirection>,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+    //minutes between two pulls of the object
+    #[serde(default = "default_schedule_minutes")]
+    pub schedule_minutes: i32,
+    //rows of records deleted in salesforce are deleted instead of marked deleted
+    #[serde(default)]
+    pub hard_delete: bool,
+    //pushes local changes, only used when the object is created
+    #[serde(default = "default_db_sync")]
+    pub db_sync: bool,
+}
+
+impl Default for ObjectSettings {
+    fn default() -> ObjectSettings {
+        ObjectSettings {
+            fields: Default::default(),
+            filter: None,
+            unmatched: Default::default(),
+            direction: Default::default(),
+            field_directions: HashMap::new(),
+            conflict: Default::default(),
+            schedule_minutes: default_schedule_minutes(),
+            hard_delete: false,
+            db_sync: default_db_sync(),
+        }
+    }
+}
+
+//applied when a row changed in postgres and salesforce since it was last pulled
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    SalesforceWins,
+    DbWins,
+    NewestWins,
+    //keep both versions in config.conflicts and flag the row for manual review
+    Park,
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> ConflictPolicy {
+        ConflictPolicy::SalesforceWins
+    }
+}
+
+impl ConflictPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ConflictPolicy::SalesforceWins => "salesforce_wins",
+            ConflictPolicy::DbWins => "db_wins",
+            ConflictPolicy::NewestWins => "newest_wins",
+            ConflictPolicy::Park => "park",
+        }
+    }
+
+    pub fn from_str(policy: &str) -> ConflictPolicy {
+        match policy {
+            "db_wins" => ConflictPolicy::DbWins,
+            "newest_wins" => ConflictPolicy::NewestWins,
+            "park" => ConflictPolicy::Park,
+            _ => ConflictPolicy::SalesforceWins,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncDirection {
+    Both,
+    SfToDb,
+    DbToSf,
+}
+
+impl Default for SyncDirection {
+    fn default() -> SyncDirection {
+        SyncDirection::Both
+    }
+}
+
+impl SyncDirection {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SyncDirection::Both => "both",
+            SyncDirection::SfToDb => "sf_to_db",
+            SyncDirection::DbToSf => "db_to_sf",
+        }
+    }
+
+    pub fn from_str(direction: &str) -> SyncDirection {
+        match direction {
+            "sf_to_db" => SyncDirection::SfToDb,
+            "db_to_sf" => SyncDirection::DbToSf,
+            _ => SyncDirection::Both,
+        }
+    }
+
+    pub fn pulls(&self) -> bool {
+        *self != SyncDirection::DbToSf
+    }
+
+    pub fn pushes(&self) -> bool {
+        *self != SyncDirection::SfToDb
+    }
+
+    //direction of a single field, an override wins over the object direction
+    pub fn for_field(&self, overrides: &HashMap<String, SyncDirection>, field_name: &str) -> SyncDirection {
+        overrides.iter()
+            .find(|&(name, _)| name.eq_ignore_ascii_case(field_name))
+            .map(|(_, direction)| *direction)
+            .unwrap_or(*self)
+    }
+}
+
+//what happens to local rows whose records no longer match the filter
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnmatchedPolicy {
+    Keep,
+    Purge,
+}
+
+impl Default for UnmatchedPolicy {
+    fn default() -> UnmatchedPolicy {
+        UnmatchedPolicy::Keep
+    }
+}
+
+impl UnmatchedPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            UnmatchedPolicy::Keep => "keep",
+            UnmatchedPolicy::Purge => "purge",
+        }
+    }
+
+    pub fn from_str(policy: &str) -> UnmatchedPolicy {
+        match policy {
+            "purge" => UnmatchedPolicy::Purge,
+            _ => UnmatchedPolicy::Keep,
+        }
+    }
+}
+
+//field names or glob patterns like *__c, an empty include list selects every field
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldSelection {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+//how address and location fields are stored, fixed per object at setup
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompoundMode {
+    //one column per component field
+    Split,
+    //the whole compound value in one jsonb column
+    Json,
+}
+
+impl Default for CompoundMode {
+    fn default() -> CompoundMode {
+        CompoundMode::Split
+    }
+}
+
+impl CompoundMode {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CompoundMode::Split => "split",
+            CompoundMode::Json => "json",
+        }
+    }
+
+    pub fn from_str(mode: &str) -> CompoundMode {
+        match mode {
+            "json" => CompoundMode::Json,
+            _ => CompoundMode::Split,
+        }
+    }
+}
+
+fn default_overlap_secs() -> i64 {
+    60
+}
+
+fn default_schema_check_interval() -> u64 {
+    3600
+}
+
+fn default_outbox_batch_size() -> i64 {
+    500
+}
+
+fn default_outbox_lease_secs() -> i64 {
+    300
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn default_schedule_minutes() -> i32 {
+    1
+}
+
+fn default_db_sync() -> bool {
+    true
+}
+
+fn default_max_attempts() -> i32 {
+    5
+}
+
+fn default_base_delay_secs() -> i64 {
+    60
+}
+
+fn default_max_delay_secs() -> i64 {
+    3600
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    //host:port the endpoint listens on
+    pub address: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    #[serde(default)]
+    pub level: LogLevel,
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: Default::default(),
+            sinks: default_sinks(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Default for LogLevel {
+    fn default() -> LogLevel {
+        LogLevel::Info
+    }
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    Console,
+    //plain text lines, the file is rotated to <path>.1 .. <path>.<keep> once it reaches max_bytes
+    File {
+        path: String,
+        #[serde(default = "default_max_bytes")]
+        max_bytes: u64,
+        #[serde(default = "default_keep")]
+        keep: u32,
+    },
+    //one json object per line, written to stderr without a path
+    JsonLines {
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default = "default_max_bytes")]
+        max_bytes: u64,
+        #[serde(default = "default_keep")]
+        keep: u32,
+    },
+}
+
+fn default_sinks() -> Vec<SinkConfig> {
+    vec!(SinkConfig::Console)
+}
+
+fn default_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_keep() -> u32 {
+    5
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -28,20 +541,54 @@ pub struct Config {
     pub salesforce: SalesforceConfig,
     pub sync: SyncConfig,
     pub db: DbConfig,
+    #[serde(default)]
+    pub logging: LogConfig,
+    //the /metrics endpoint is only served when configured
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
 }
 
 impl Config {
     pub fn new(file: &str) -> Result<Self, String> {
 
         let mut file = File::open(file)
-            .map_err(|err| format!("Problem while loading config: {}", err))
-            .unwrap();
+            .map_err(|err| format!("Problem while loading config: {}", err))?;
         let mut input = String::new();
-        let size = file.read_to_string(&mut input);
-        println!("Read {:?} bytes", size);
+        file.read_to_string(&mut input)
+            .map_err(|err| format!("Problem while loading config: {}", err))?;
         let config: Config = serde_json::from_str(input.as_str())
             .map_err(|e| format!("Could not parse JSON: {}", e))?;
 
         return Ok(config);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync_config() -> SyncConfig {
+        serde_json::from_str(r#"{"timeout": 60, "objects": {"Account": {"filter": "Type = 'Customer'", "hard_delete": true}}}"#)
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_object_settings_ignoring_case() {
+        let config = sync_config();
+        for name in &["Account", "account", "ACCOUNT"] {
+            let settings = config.get_object_settings(name);
+            assert_eq!(settings.filter, Some("Type = 'Customer'".to_owned()));
+            assert!(settings.hard_delete);
+        }
+    }
+
+    #[test]
+    fn defaults_settings_of_undeclared_objects() {
+        let config = sync_config();
+        assert!(config.find_object_settings("Contact").is_none());
+        let settings = config.get_object_settings("Contact");
+        assert_eq!(settings.filter, None);
+        assert!(!settings.hard_delete);
+        assert!(settings.db_sync);
+    }
+}
@@ -0,0 +1,45 @@
+use chrono::prelude::*;
+use config::ConflictPolicy;
+
+pub const STATE_CONFLICT: &str = "CONFLICT";
+//set manually once a parked row was fixed, the row is pushed without another check
+pub const STATE_RESOLVED: &str = "RESOLVED";
+
+//local state of a row with changes that were not pushed yet
+pub struct PendingRow {
+    pub id: i32,
+    pub modified: Option<NaiveDateTime>,
+    pub sf_modstamp: Option<NaiveDateTime>,
+    pub state: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Resolution {
+    TakeSalesforce,
+    KeepDb,
+    Park,
+}
+
+//db_modified is the local change, sf_modstamp the SystemModstamp of the changed salesforce record
+pub fn resolve(policy: ConflictPolicy, db_modified: Option<NaiveDateTime>, sf_modstamp: Option<&DateTime<Utc>>)
+    -> Resolution {
+    match policy {
+        ConflictPolicy::SalesforceWins => Resolution::TakeSalesforce,
+        ConflictPolicy::DbWins => Resolution::KeepDb,
+        ConflictPolicy::Park => Resolution::Park,
+        ConflictPolicy::NewestWins => {
+            match (db_modified, sf_modstamp) {
+                (Some(db_modified), Some(sf_modstamp)) if db_modified > sf_modstamp.naive_utc() => Resolution::KeepDb,
+                _ => Resolution::TakeSalesforce,
+            }
+        }
+    }
+}
+
+//a missing baseline means the row was never pulled with a SystemModstamp
+pub fn sf_changed(baseline: Option<NaiveDateTime>, sf_modstamp: Option<&DateTime<Utc>>) -> bool {
+    match (baseline, sf_modstamp) {
+        (Some(baseline), Some(sf_modstamp)) => sf_modstamp.naive_utc() > baseline,
+        _ => true,
+    }
+}
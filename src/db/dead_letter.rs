@@ -0,0 +1,38 @@
+use chrono::NaiveDateTime;
+use postgres::rows::Row;
+
+pub const STATE_ERROR: &str = "ERROR";
+//retries are exhausted or the error is not retryable, the row waits in config.dead_letters
+pub const STATE_FAILED: &str = "FAILED";
+//failed pushes, failed deletes are listed with the outbox op
+pub const OP_PUSH: &str = "PUSH";
+
+//dead letters of an object, or of a single row when a record id is given
+pub const DELETE_QUERY: &str = "DELETE FROM config.dead_letters WHERE object_name = $1 AND ($2::int IS NULL OR record_id = $2)";
+
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub id: i32,
+    pub object_name: String,
+    pub record_id: i32,
+    pub sfid: Option<String>,
+    pub error: String,
+    pub attempts: i32,
+    pub created: NaiveDateTime,
+    pub op: String,
+}
+
+impl DeadLetter {
+    pub fn new(row: &Row) -> DeadLetter {
+        DeadLetter {
+            id: row.get(0),
+            object_name: row.get(1),
+            record_id: row.get(2),
+            sfid: row.get(3),
+            error: row.get(4),
+            attempts: row.get(5),
+            created: row.get(6),
+            op: row.get(7),
+        }
+    }
+}
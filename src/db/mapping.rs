@@ -1,32 +1,57 @@
 use std::collections::HashMap;
+use salesforce::objects::Field;
 
 static DEFAULT: &str = "varchar";
 
 lazy_static! {
     static ref TYPEMAP: HashMap<String, &'static str> = {
         let mut m = HashMap::new();
-        m.insert("id".to_owned(), "varchar");
+        m.insert("id".to_owned(), "varchar(18)");
+        m.insert("reference".to_owned(), "varchar(18)");
         m.insert("string".to_owned(), "varchar");
         m.insert("picklist".to_owned(), "varchar");
-        m.insert("double".to_owned(), "double precision");
-        m.insert("currency".to_owned(), "double precision");
-        m.insert("percent".to_owned(), "double precision");
+        m.insert("combobox".to_owned(), "varchar");
+        m.insert("encryptedstring".to_owned(), "varchar");
+        m.insert("email".to_owned(), "varchar");
+        m.insert("phone".to_owned(), "varchar");
+        m.insert("url".to_owned(), "varchar");
+        m.insert("textarea".to_owned(), "text");
+        m.insert("anytype".to_owned(), "text");
+        m.insert("multipicklist".to_owned(), "text[]");
+        m.insert("base64".to_owned(), "bytea");
+        m.insert("double".to_owned(), "numeric");
+        m.insert("currency".to_owned(), "numeric");
+        m.insert("percent".to_owned(), "numeric");
         m.insert("int".to_owned(), "integer");
+        m.insert("long".to_owned(), "bigint");
         m.insert("datetime".to_owned(), "timestamp");
         m.insert("date".to_owned(), "date");
+        m.insert("time".to_owned(), "time");
         m.insert("boolean".to_owned(), "boolean");
+        m.insert("complexvalue".to_owned(), "jsonb");
+        //only stored when compound fields are kept in a single column
+        m.insert("address".to_owned(), "jsonb");
+        m.insert("location".to_owned(), "jsonb");
         m
     };
 }
 
-pub fn sf_type_mapping(field_type: &String, length: u32) -> Result<String, String> {
-    let db_type = TYPEMAP.get(field_type).unwrap_or(&DEFAULT);
+pub fn sf_type_mapping(field: &Field) -> Result<String, String> {
+    let field_type = field.sf_type.to_lowercase();
+    let db_type = TYPEMAP.get(&field_type).unwrap_or(&DEFAULT);
     match *db_type {
         "varchar" => {
-            if length > 255 {
+            //types without a length would end up as varchar(0)
+            if field.length == 0 || field.length > 255 {
                return  Ok(String::from("text"));
             }
-            Ok(format!("{}({})",db_type, length))
+            Ok(format!("{}({})",db_type, field.length))
+        },
+        "numeric" => {
+            if field.precision == 0 {
+                return Ok(String::from("double precision"));
+            }
+            Ok(format!("numeric({},{})", field.precision, field.scale))
         },
         _ => Ok(db_type.to_string())
     }
@@ -1,20 +1,34 @@
+pub mod conflict;
+pub mod dead_letter;
 pub mod mapping;
 pub mod objects;
+pub mod outbox;
 pub mod query;
 pub mod record;
+pub mod value;
 
 use postgres::rows::Rows;
-use salesforce::objects::{SObjectDescribe, Field, SObjectRowResultWrapper};
+use salesforce::objects::{SObjectSetup, Field, SObjectRowResultWrapper, ColumnValue};
 use serde_json;
 use std::collections::HashMap;
+use chrono::prelude::*;
+use time::Duration;
 use r2d2_postgres::{TlsMode, PostgresConnectionManager};
 use r2d2::{Pool};
 use r2d2::config::Builder;
-use config::DbConfig;
+use config::{DbConfig, CompoundMode, ObjectSettings, RetryConfig};
 use fallible_iterator::FallibleIterator;
-use db::query::{CreateQueryBuilder, UpdateQueryBuilder, escape_single_quote, get_lock_query};
-use db::objects::ObjectConfig;
-use db::record::Record;
+use postgres::types::ToSql;
+use db::query::{CreateQueryBuilder, InsertQueryBuilder, UpdateQueryBuilder, AlterQueryBuilder, quote_identifier,
+                quote_table, get_lock_name, get_lock_query};
+use db::objects::{ObjectConfig, OBJECT_COLUMNS};
+use salesforce::compound;
+use db::record::{Record, RECORD_COLUMNS};
+use db::conflict::{PendingRow, Resolution};
+use db::outbox::{OutboxEntry, CLAIM_QUERY};
+use db::dead_letter::DeadLetter;
+use salesforce::errors::SalesforceError;
+use logging::Event;
 
 pub struct Db {
     pub pool: Pool<PostgresConnectionManager>,
@@ -22,103 +36,242 @@ pub struct Db {
 }
 
 impl Db {
-    pub fn new(db_config: &'static DbConfig) -> Db {
+    pub fn new(db_config: &'static DbConfig) -> Result<Db, String> {
         let config = Builder::new().pool_size(1).build();
-        let manager = PostgresConnectionManager::new(db_config.url.clone(), TlsMode::None).unwrap();
+        let manager = PostgresConnectionManager::new(db_config.url.clone(), TlsMode::None)
+            .map_err(|err| format!("DB Error: Invalid url - {}", err))?;
         let pool = Pool::new(config, manager)
-            .map_err(|err| panic!("DB Error: Cannot connect - {}", err.to_string()))
-            .unwrap();
-        Db { 
+            .map_err(|err| format!("DB Error: Cannot connect - {}", err))?;
+        Ok(Db { 
             pool: pool,
             config: db_config
-        }
+        })
     }
 
-    pub fn save_config_data(&self, item: &SObjectDescribe) {
-        let field_json = serde_json::to_string(&item.fields).unwrap();
-        let conn = self.pool.get().unwrap();
-        conn.execute("INSERT INTO config.objects (name, db_name, fields, last_sync_time) VALUES ($1, $2, $3, now())",
-                 &[&item.name, &item.name.to_lowercase(), &field_json]).unwrap();
+    //written once the table is loaded, the first pull starts at last_sync_time
+    pub fn save_config_data(&self, object: &SObjectSetup, last_sync_time: &NaiveDateTime) -> Result<(), String> {
+        let item = object.describe;
+        let settings = object.settings;
+        let field_json = serde_json::to_string(&item.fields).map_err(|err| err.to_string())?;
+        let selection_json = serde_json::to_string(&settings.fields).map_err(|err| err.to_string())?;
+        let directions_json = serde_json::to_string(&settings.field_directions).map_err(|err| err.to_string())?;
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute("INSERT INTO config.objects (name, db_name, fields, compound_mode, field_selection, record_filter, unmatched_policy, sync_direction, field_directions, conflict_policy, schedule_minutes, hard_delete, last_sync_time) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+                 &[&item.name, &item.name.to_lowercase(), &field_json, &object.compound_mode.as_str(), &selection_json,
+                   &settings.filter, &settings.unmatched.as_str(), &settings.direction.as_str(), &directions_json,
+                   &settings.conflict.as_str(), &settings.schedule_minutes, &settings.hard_delete, last_sync_time])
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    //settings of an object that is already set up, the table itself is left to reconcile_object
+    pub fn update_object_settings(&self, id: i32, settings: &ObjectSettings) -> Result<u64, String> {
+        let selection_json = serde_json::to_string(&settings.fields).map_err(|err| err.to_string())?;
+        let directions_json = serde_json::to_string(&settings.field_directions).map_err(|err| err.to_string())?;
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute("UPDATE config.objects SET field_selection = $1, record_filter = $2, unmatched_policy = $3, sync_direction = $4, field_directions = $5, conflict_policy = $6, schedule_minutes = $7, hard_delete = $8, updated = now() WHERE id = $9",
+                     &[&selection_json, &settings.filter, &settings.unmatched.as_str(), &settings.direction.as_str(),
+                       &directions_json, &settings.conflict.as_str(), &settings.schedule_minutes, &settings.hard_delete, &id])
+            .map_err(|err| err.to_string())
     }
 
-    pub fn create_object_table(&self, object_name: &String, fields: &Vec<Field>) {
-        let table_name = format!("salesforce.{}",object_name);
-        let mut query_builder = CreateQueryBuilder::new(&table_name);
+    pub fn create_object_table(&self, object_name: &String, fields: &Vec<Field>, compound_mode: CompoundMode)
+        -> Result<(), String> {
+        let mut query_builder = CreateQueryBuilder::new(&quote_table(object_name));
         query_builder.add_field("id", "SERIAL PRIMARY KEY".to_string());
         query_builder.add_field( "sfid", "varchar(18)".to_string());
-        for field in fields {
-            if field.name == "Id" || field.sf_type == "address" {
+        for field in compound::column_fields(fields, compound_mode) {
+            if field.name == "Id" {
                 continue;
             }
-            let mapping = mapping::sf_type_mapping(&field.sf_type, field.length).unwrap();
+            let mapping = mapping::sf_type_mapping(field)?;
             query_builder.add_field( field.name.as_str(), mapping);
         }
         query_builder.add_field("_s_error", "TEXT".to_string());
         query_builder.add_field("_s_state", "varchar(20) DEFAULT 'OK'".to_string());
         query_builder.add_field("_s_created", "TIMESTAMP DEFAULT NOW()".to_string());
         query_builder.add_field("_s_updated", "TIMESTAMP".to_string());
+        query_builder.add_field("_s_deleted", "TIMESTAMP".to_string());
+        query_builder.add_field("_s_sf_modstamp", "TIMESTAMP".to_string());
+        query_builder.add_field("_s_modified", "TIMESTAMP".to_string());
+        query_builder.add_field("_s_attempts", "INTEGER DEFAULT 0".to_string());
+        query_builder.add_field("_s_retry_at", "TIMESTAMP".to_string());
         let query = query_builder.build();
         
         // println!("{}", query);
-        let conn = self.pool.get().unwrap();
-        conn.execute(query.as_str(), &[]).unwrap();
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute(query.as_str(), &[]).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    //removes the table of an object whose setup failed before its config row was written
+    pub fn drop_object_table(&self, object_name: &String) -> Result<(), String> {
+        let query = format!("DROP TABLE IF EXISTS {}", quote_table(object_name));
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute(query.as_str(), &[]).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    //applies describe changes to an existing object table, returns the changes made
+    pub fn alter_object_table(&self, object: &ObjectConfig, fields: &Vec<Field>, drop_removed: bool)
+        -> Result<Vec<String>, String> {
+        let mut builder = AlterQueryBuilder::new(&quote_table(&object.name));
+        let mut changes = vec!();
+        let old_fields = compound::column_fields(&object.fields, object.compound_mode);
+        let new_fields = compound::column_fields(fields, object.compound_mode);
+        for field in &new_fields {
+            if field.name == "Id" {
+                continue;
+            }
+            let mapping = mapping::sf_type_mapping(field)?;
+            match old_fields.iter().find(|old| old.name == field.name) {
+                None => {
+                    builder.add_column(&field.name, &mapping);
+                    changes.push(format!("added column {} {}", field.name.to_lowercase(), mapping));
+                },
+                Some(old) => {
+                    let old_mapping = mapping::sf_type_mapping(old)?;
+                    if old_mapping != mapping {
+                        builder.alter_column_type(&field.name, &mapping);
+                        changes.push(format!("changed column {} from {} to {}",
+                                             field.name.to_lowercase(), old_mapping, mapping));
+                    }
+                }
+            }
+        }
+        for old in &old_fields {
+            if old.name == "Id" {
+                continue;
+            }
+            if new_fields.iter().any(|field| field.name == old.name) {
+                continue;
+            }
+            //the field may only be hidden by a permission change, its data is kept by default
+            if drop_removed {
+                builder.drop_column(&old.name);
+                changes.push(format!("dropped column {}", old.name.to_lowercase()));
+            } else {
+                changes.push(format!("kept column {}, the field is no longer synced", old.name.to_lowercase()));
+            }
+        }
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let trans = conn.transaction().map_err(|err| err.to_string())?;
+        if !builder.is_empty() {
+            trans.execute(&builder.build(), &[]).map_err(|err| err.to_string())?;
+        }
+        //fields missing from the stored list are neither pulled nor pushed
+        let field_json = serde_json::to_string(fields).map_err(|err| err.to_string())?;
+        trans.execute("UPDATE config.objects SET fields = $1, updated = now() WHERE id = $2",
+                      &[&field_json, &object.id])
+            .map_err(|err| err.to_string())?;
+        trans.commit().map_err(|err| err.to_string())?;
+        Ok(changes)
     }
 
-    pub fn add_channel_trigger(&self, object_name: &String) {
+    pub fn add_channel_trigger(&self, object_name: &String) -> Result<(), String> {
         let query = format!(
-            "CREATE TRIGGER {}_notify
-         AFTER INSERT OR UPDATE 
-         ON salesforce.{}
+            "CREATE TRIGGER {}
+         AFTER INSERT OR UPDATE OR DELETE
+         ON {}
          FOR EACH ROW
          EXECUTE PROCEDURE salesforce.notify_change();",
-            object_name,
-            object_name
+            quote_identifier(&format!("{}_notify", object_name.to_lowercase())),
+            quote_table(object_name)
         );
-        let conn = self.pool.get().unwrap();
-        conn.execute(query.as_str(), &[]).unwrap();
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute(query.as_str(), &[]).map_err(|err| err.to_string())?;
+        //local changes are timestamped to detect conflicts with salesforce
+        let query = format!(
+            "CREATE TRIGGER {}
+         BEFORE INSERT OR UPDATE
+         ON {}
+         FOR EACH ROW
+         EXECUTE PROCEDURE salesforce.mark_modified();",
+            quote_identifier(&format!("{}_modified", object_name.to_lowercase())),
+            quote_table(object_name)
+        );
+        conn.execute(query.as_str(), &[]).map_err(|err| err.to_string())?;
+        Ok(())
     }
 
+    //objects due for a pull, the schedule of an object wins over a shorter interval, a negative interval returns all
     pub fn get_selected_objects(&self, interval: i16) -> Result<Vec<ObjectConfig>, String> {
-        let conn = self.pool.get().unwrap();
-        let query = format!("SELECT id, name, fields, last_sync_time FROM config.objects WHERE last_sync_time < current_timestamp - interval '{} minutes'",
-                            interval);
-        let rows: Rows = conn.query(query.as_str(), &[]).unwrap();
-        let result = rows.iter()
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let query = format!("SELECT {} FROM config.objects WHERE $1::int < 0 OR last_sync_time < timezone('utc', now()) - GREATEST($1::int, schedule_minutes) * interval '1 minute'",
+                            OBJECT_COLUMNS);
+        let rows: Rows = conn.query(&query, &[&(interval as i32)]).map_err(|err| err.to_string())?;
+        rows.iter()
             .map(|row| {
                      let name: String = row.get(1);
-                     let query = format!("SELECT count(*)::int FROM salesforce.{:?}",
-                                         name.to_lowercase());
-                     let count_rows: Rows = conn.query(query.as_str(), &[]).unwrap();
+                     let query = format!("SELECT count(*)::int FROM {}",
+                                         quote_table(&name));
+                     let count_rows: Rows = conn.query(query.as_str(), &[]).map_err(|err| err.to_string())?;
                      let count: i32 = count_rows.get(0).get(0);
-                     ObjectConfig::new(row.get(0), name, count as u32, row.get(2))
+                     Ok(ObjectConfig::from_row(&row, count as u32))
                  })
-            .collect();
-        Ok(result)
+            .collect()
+    }
+
+    //watermark of every object, for the watermark age metric
+    pub fn get_watermarks(&self) -> Result<Vec<(String, Option<NaiveDateTime>)>, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let rows = conn.query("SELECT name, COALESCE(watermark, last_sync_time) FROM config.objects", &[])
+            .map_err(|err| err.to_string())?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    pub fn count_pending_outbox(&self) -> Result<i64, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let rows = conn.query("SELECT count(*) FROM config.outbox WHERE done IS NULL", &[])
+            .map_err(|err| err.to_string())?;
+        Ok(rows.get(0).get(0))
     }
 
-    pub fn get_object_data_by_id(&self, object_name: &String , ids: &Vec<i32>) 
+    //rows per object waiting for a retry of a failed push
+    pub fn count_error_rows(&self) -> Result<Vec<(String, i64)>, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let rows = conn.query("SELECT name FROM config.objects", &[]).map_err(|err| err.to_string())?;
+        let mut counts = vec!();
+        for row in rows.iter() {
+            let name: String = row.get(0);
+            let query = format!("SELECT count(*) FROM {} WHERE _s_state = $1", quote_table(&name));
+            let count_rows = conn.query(&query, &[&dead_letter::STATE_ERROR]).map_err(|err| err.to_string())?;
+            counts.push((name, count_rows.get(0).get(0)));
+        }
+        Ok(counts)
+    }
+
+    pub fn get_object_config(&self, object_name: &str) -> Result<ObjectConfig, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let query = format!("SELECT {} FROM config.objects WHERE db_name = $1", OBJECT_COLUMNS);
+        let rows = conn.query(&query, &[&object_name]).map_err(|err| err.to_string())?;
+        let row = rows.iter().next().ok_or(format!("Object {} not configured", object_name))?;
+        Ok(ObjectConfig::from_row(&row, 0))
+    }
+
+    pub fn get_object_data_by_id(&self, config: &ObjectConfig, ids: &Vec<i32>)
         -> Vec<Record>{
         let conn = self.pool.get().unwrap();
-        let query = "SELECT id, db_name, fields FROM config.objects WHERE db_name = $1";
-        let rows = conn.query(query, &[object_name]).unwrap();
-        let row = rows.iter().next().unwrap();
-        let config: ObjectConfig = ObjectConfig::new(row.get(0), row.get(1), ids.len() as u32, row.get(2));
-        let fieldnames = config.get_db_field_names();
-        let mut query = format!("SELECT id, sfid, {} FROM salesforce.{}", fieldnames.join(","), object_name);
+        let object_name = &config.name;
+        let fieldnames: Vec<String> = config.get_db_field_names()
+            .iter()
+            .map(|name| quote_identifier(name))
+            .collect();
+        let mut query = format!("SELECT {}, {} FROM {}", RECORD_COLUMNS, fieldnames.join(","), quote_table(object_name));
+        let mut params: Vec<&ToSql> = vec!();
         if ids.len() > 0 {
-            query.push_str(" WHERE id IN(");
-            let mut tmp = vec!();
-            ids.iter().for_each(|id|{
-                tmp.push(format!("{}",id));
-            });
-            query.push_str(tmp.join(",").as_str());
-            query.push_str(")");
+            query.push_str(" WHERE id = ANY($1)");
+            params.push(ids);
         }
-        let result = conn.query(&query, &[]).unwrap();
+        let result = conn.query(&query, &params).unwrap();
         let mut res = vec!();
         for row in result.iter() {
             //println!("{:?}",row);
-            let record = Record::new(&row);
+            let mut record = Record::new(&row);
+            record.expand_compounds(config);
             //println!("{}",record.get_json());
             res.push(record);
         }
@@ -127,36 +280,253 @@ impl Db {
 
     pub fn update_last_sync_time(&self, id: i32) {
         let conn = self.pool.get().unwrap();
-        let _result = conn.query("Update config.objects set last_sync_time = now() WHERE id = $1",
+        let _result = conn.query("Update config.objects set last_sync_time = timezone('utc', now()) WHERE id = $1",
                                  &[&id]);
     }
 
-    pub fn set_error_state(&self, object_name: &str, id: &i32, error: &str) {
-        let id_str = id.to_string();
-        let error_str = format!("'{}'", error);
-        let table_name = format!("salesforce.{}",object_name);
-        let mut builder = UpdateQueryBuilder::new(&table_name);
-        builder.add_field("_s_error", &error_str);
-        builder.add_field("_s_state", "'ERROR'");
-        builder.add_and_where("id", &id_str, "=");
+    //only called once the rows up to the watermark are stored
+    //the next pull fetches every record again
+    pub fn reset_watermark(&self, id: i32) -> Result<u64, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute("UPDATE config.objects SET watermark = 'epoch', last_sync_time = 'epoch' WHERE id = $1", &[&id])
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn update_watermark(&self, id: i32, watermark: &DateTime<Utc>) {
+        let conn = self.pool.get().unwrap();
+        let _result = conn.execute("UPDATE config.objects SET watermark = $1 WHERE id = $2",
+                                   &[&watermark.naive_utc(), &id])
+            .map_err(|err| Event::error("Error updating watermark").with_error(&err.to_string()).emit());
+    }
+
+    //schedules a retry of a failed push, or moves the row to the dead letters
+    pub fn set_error_state(&self, object_name: &str, id: &i32, error: &SalesforceError, retry: &RetryConfig) {
+        let conn = match self.pool.get() {
+            Ok(conn) => conn,
+            Err(err) => {
+                Event::error("Error connecting to the db").with_object(object_name).with_record(*id)
+                    .with_error(&err.to_string()).emit();
+                return;
+            }
+        };
+        let query = format!("SELECT COALESCE(_s_attempts, 0) FROM {} WHERE id = $1", quote_table(object_name));
+        let attempts: i32 = match conn.query(&query, &[id]) {
+            Ok(ref rows) if !rows.is_empty() => rows.get(0).get::<_, i32>(0) + 1,
+            Ok(_) => return,
+            Err(err) => {
+                Event::error("Error reading attempts").with_object(object_name).with_record(*id)
+                    .with_error(&err.to_string()).emit();
+                return;
+            }
+        };
+        drop(conn);
+        let transient = error.is_transient();
+        let error = error.to_string();
+        if !transient || attempts >= retry.max_attempts {
+            let _ = self.move_to_dead_letters(object_name, id, &error, attempts)
+                .map_err(|err| Event::error("Error moving row to dead letters").with_object(object_name)
+                    .with_record(*id).with_error(&err).emit());
+            return;
+        }
+        let retry_at = Utc::now().naive_utc() + Duration::seconds(retry.backoff_secs(attempts));
+        let mut builder = UpdateQueryBuilder::new(&quote_table(object_name));
+        builder.add_field("_s_error", &error);
+        builder.add_field("_s_state", &dead_letter::STATE_ERROR);
+        builder.add_field("_s_attempts", &attempts);
+        builder.add_field("_s_retry_at", &retry_at);
+        builder.add_and_where("id", id, "=");
         let query = builder.build();
-        println!("{}", query);
-        let _ = self.query_with_lock(&query, object_name);
+        Event::debug(&query).with_object(object_name).emit();
+        let _ = self.query_with_lock(&query, builder.params(), object_name);
+    }
+
+    fn move_to_dead_letters(&self, object_name: &str, id: &i32, error: &str, attempts: i32) -> Result<u64, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let query = format!("INSERT INTO config.dead_letters (object_name, record_id, sfid, error, attempts, data) SELECT $1::varchar, t.id, t.sfid, $3::text, $4::int, row_to_json(t)::jsonb FROM {} t WHERE t.id = $2",
+                            quote_table(object_name));
+        conn.execute(&query, &[&object_name.to_lowercase(), id, &error, &attempts])
+            .map_err(|err| err.to_string())?;
+        drop(conn);
+        //the counter starts over once the row is requeued
+        let query = format!("UPDATE {} SET _s_state = $1, _s_error = $2, _s_attempts = 0, _s_retry_at = NULL WHERE id = $3",
+                            quote_table(object_name));
+        self.query_with_lock(&query, &[&dead_letter::STATE_FAILED, &error, id], object_name)
     }
 
-    pub fn upsert_object_rows(&self, wrapper: &SObjectRowResultWrapper) -> Result<u64, String> {
+    //adds rows whose retry is due to the outbox
+    pub fn schedule_retries(&self) -> Result<u64, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let rows = conn.query("SELECT db_name FROM config.objects", &[]).map_err(|err| err.to_string())?;
+        drop(conn);
         let mut count = 0;
-        for id in wrapper.rows.keys() {
-            let mut result =
-                try!{
-                self.update_rows(id, &wrapper.object_name, &wrapper.rows.get(id).unwrap())
-            };
-            if result == 0 {
-                result =
-                    try!{
-                    self.insert_rows(&wrapper.object_name, &wrapper.rows.get(id).unwrap())
+        for row in rows.iter() {
+            let object_name: String = row.get(0);
+            let query = format!("WITH due AS (
+                    UPDATE {} SET _s_retry_at = NULL
+                    WHERE _s_state = $2 AND _s_retry_at <= timezone('utc', now())
+                    RETURNING id, sfid)
+                INSERT INTO config.outbox (table_name, record_id, sfid, op, txid)
+                SELECT $1, id, sfid, 'RETRY', txid_current() FROM due",
+                quote_table(&object_name));
+            count += self.query_with_lock(&query, &[&object_name, &dead_letter::STATE_ERROR], &object_name)?;
+        }
+        //the rows of failed deletes are gone, their entries are recreated from config.failed_deletes
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        count += conn.execute("WITH due AS (
+                UPDATE config.failed_deletes SET retry_at = NULL
+                WHERE state = $1 AND retry_at <= timezone('utc', now())
+                RETURNING object_name, record_id, sfid)
+            INSERT INTO config.outbox (table_name, record_id, sfid, op, txid)
+            SELECT object_name, record_id, sfid, $2, txid_current() FROM due",
+                              &[&dead_letter::STATE_ERROR, &outbox::OP_DELETE])
+            .map_err(|err| err.to_string())?;
+        Ok(count)
+    }
+
+    pub fn list_dead_letters(&self, object_name: Option<&str>) -> Result<Vec<DeadLetter>, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let object_name = object_name.map(|name| name.to_lowercase());
+        let rows = conn.query("SELECT id, object_name, record_id, sfid, error, attempts, created, $2::varchar FROM config.dead_letters WHERE $1::varchar IS NULL OR object_name = $1
+                UNION ALL
+                SELECT id, object_name, record_id, sfid, error, attempts, created, $3::varchar FROM config.failed_deletes WHERE state = $4 AND ($1::varchar IS NULL OR object_name = $1)
+                ORDER BY created",
+                              &[&object_name, &dead_letter::OP_PUSH, &outbox::OP_DELETE, &dead_letter::STATE_FAILED])
+            .map_err(|err| err.to_string())?;
+        Ok(rows.iter().map(|row| DeadLetter::new(&row)).collect())
+    }
+
+    //gives dead lettered rows of an object, or a single row, a new set of attempts
+    pub fn requeue_dead_letters(&self, object_name: &str, record_id: Option<i32>) -> Result<u64, String> {
+        let lock_name = get_lock_name(object_name);
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        //the rows and their dead letters change together, a rollback also resets the lock
+        let trans = conn.transaction().map_err(|err| err.to_string())?;
+        trans.execute(get_lock_query(), &[&lock_name, &"lock"]).map_err(|err| err.to_string())?;
+        let query = format!("UPDATE {} SET _s_state = $1, _s_attempts = 0, _s_retry_at = timezone('utc', now()) WHERE _s_state = $2 AND id IN (
+                SELECT record_id FROM config.dead_letters WHERE object_name = $3 AND ($4::int IS NULL OR record_id = $4))",
+            quote_table(object_name));
+        let count = trans.execute(&query, &[&dead_letter::STATE_ERROR, &dead_letter::STATE_FAILED,
+                                            &object_name.to_lowercase(), &record_id])
+            .map_err(|err| err.to_string())?;
+        trans.execute(dead_letter::DELETE_QUERY, &[&object_name.to_lowercase(), &record_id])
+            .map_err(|err| err.to_string())?;
+        let deletes = trans.execute("UPDATE config.failed_deletes SET state = $1, attempts = 0, retry_at = timezone('utc', now())
+                WHERE state = $2 AND object_name = $3 AND ($4::int IS NULL OR record_id = $4)",
+                                    &[&dead_letter::STATE_ERROR, &dead_letter::STATE_FAILED, &object_name.to_lowercase(), &record_id])
+            .map_err(|err| err.to_string())?;
+        trans.execute(get_lock_query(), &[&lock_name, &""]).map_err(|err| err.to_string())?;
+        trans.commit().map_err(|err| err.to_string())?;
+        Ok(count + deletes)
+    }
+
+    //gives up on the local changes of dead lettered rows, the next pull overwrites them
+    pub fn discard_dead_letters(&self, object_name: &str, record_id: Option<i32>) -> Result<u64, String> {
+        let lock_name = get_lock_name(object_name);
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        //the rows and their dead letters change together, a rollback also resets the lock
+        let trans = conn.transaction().map_err(|err| err.to_string())?;
+        trans.execute(get_lock_query(), &[&lock_name, &"lock"]).map_err(|err| err.to_string())?;
+        let query = format!("UPDATE {} SET _s_state = 'OK', _s_error = NULL, _s_modified = NULL WHERE _s_state = $1 AND id IN (
+                SELECT record_id FROM config.dead_letters WHERE object_name = $2 AND ($3::int IS NULL OR record_id = $3))",
+            quote_table(object_name));
+        trans.execute(&query, &[&dead_letter::STATE_FAILED, &object_name.to_lowercase(), &record_id])
+            .map_err(|err| err.to_string())?;
+        let count = trans.execute(dead_letter::DELETE_QUERY, &[&object_name.to_lowercase(), &record_id])
+            .map_err(|err| err.to_string())?;
+        //the record stays in salesforce
+        let deletes = trans.execute("DELETE FROM config.failed_deletes WHERE state = $1 AND object_name = $2 AND ($3::int IS NULL OR record_id = $3)",
+                                    &[&dead_letter::STATE_FAILED, &object_name.to_lowercase(), &record_id])
+            .map_err(|err| err.to_string())?;
+        trans.execute(get_lock_query(), &[&lock_name, &""]).map_err(|err| err.to_string())?;
+        trans.commit().map_err(|err| err.to_string())?;
+        Ok(count + deletes)
+    }
+
+    //deleted rows cannot carry an error state, the failure is kept and retried from a separate table
+    pub fn log_failed_delete(&self, object_name: &str, id: &i32, sfid: &str, error: &SalesforceError, retry: &RetryConfig) {
+        let conn = match self.pool.get() {
+            Ok(conn) => conn,
+            Err(err) => {
+                Event::error("Error connecting to the db").with_object(object_name).with_record(*id)
+                    .with_error(&err.to_string()).emit();
+                return;
+            }
+        };
+        let previous: Option<i32> = match conn.query("SELECT attempts FROM config.failed_deletes WHERE object_name = $1 AND record_id = $2",
+                                                     &[&object_name, id]) {
+            Ok(ref rows) if !rows.is_empty() => Some(rows.get(0).get(0)),
+            Ok(_) => None,
+            Err(err) => {
+                Event::error("Error reading attempts").with_object(object_name).with_record(*id)
+                    .with_error(&err.to_string()).emit();
+                return;
+            }
+        };
+        let attempts = previous.unwrap_or(0) + 1;
+        let (state, retry_at) = if error.is_transient() && attempts < retry.max_attempts {
+            (dead_letter::STATE_ERROR, Some(Utc::now().naive_utc() + Duration::seconds(retry.backoff_secs(attempts))))
+        } else {
+            (dead_letter::STATE_FAILED, None)
+        };
+        let error = error.to_string();
+        let params: &[&ToSql] = &[&object_name, id, &sfid, &error, &attempts, &state, &retry_at];
+        let result = if previous.is_some() {
+            conn.execute("UPDATE config.failed_deletes SET sfid = $3, error = $4, attempts = $5, state = $6, retry_at = $7 WHERE object_name = $1 AND record_id = $2",
+                         params)
+        } else {
+            conn.execute("INSERT INTO config.failed_deletes (object_name, record_id, sfid, error, attempts, state, retry_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                         params)
+        };
+        let _ = result.map_err(|err| Event::error("Error logging failed delete").with_object(object_name)
+            .with_record(*id).with_error(&err.to_string()).emit());
+    }
+
+    pub fn clear_failed_deletes(&self, object_name: &str, ids: &Vec<i32>) -> Result<u64, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute("DELETE FROM config.failed_deletes WHERE object_name = $1 AND record_id = ANY($2)",
+                     &[&object_name, ids])
+            .map_err(|err| err.to_string())
+    }
+
+    //keeps both versions for manual review and flags the row
+    pub fn park_conflict(&self, object_name: &str, id: &i32, sf_version: &serde_json::Value) -> Result<u64, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let query = format!("INSERT INTO config.conflicts (object_name, record_id, sfid, db_version, sf_version) SELECT $1::varchar, t.id, t.sfid, row_to_json(t)::jsonb, $3::jsonb FROM {} t WHERE t.id = $2",
+                            quote_table(object_name));
+        conn.execute(&query, &[&object_name, id, sf_version]).map_err(|err| err.to_string())?;
+        //the pool holds a single connection
+        drop(conn);
+        let mut builder = UpdateQueryBuilder::new(&quote_table(object_name));
+        builder.add_field("_s_state", &conflict::STATE_CONFLICT);
+        builder.add_and_where("id", id, "=");
+        self.query_with_lock(&builder.build(), builder.params(), object_name)
+    }
+
+    //clears the local change marker of rows written to salesforce
+    pub fn mark_pushed(&self, object_name: &str, ids: &Vec<i32>) -> Result<u64, String> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let query = format!("UPDATE {} SET _s_modified = NULL, _s_state = 'OK', _s_error = NULL, _s_attempts = 0, _s_retry_at = NULL WHERE id = ANY($1)",
+                            quote_table(object_name));
+        self.query_with_lock(&query, &[ids], object_name)
+    }
+
+    pub fn upsert_object_rows(&self, object: &ObjectConfig, wrapper: &SObjectRowResultWrapper) -> Result<u64, String> {
+        let sfids: Vec<String> = wrapper.rows.keys().cloned().collect();
+        let pending = self.get_pending_rows(&wrapper.object_name, &sfids)?;
+        let mut count = 0;
+        for (id, row) in &wrapper.rows {
+            let modstamp = wrapper.modstamps.get(id);
+            if let Some(local) = pending.get(id) {
+                if !self.resolve_pulled_row(object, row, modstamp, local)? {
+                    continue;
                 }
             }
+            let mut result = self.update_rows(id, &wrapper.object_name, row, modstamp)?;
+            if result == 0 {
+                result = self.insert_rows(&wrapper.object_name, row, modstamp)?;
+            }
             count += result;
         }
         Ok(count)
@@ -164,79 +534,177 @@ impl Db {
 
     pub fn populate(&self, wrapper: &SObjectRowResultWrapper) -> Result<u64, String> {
         let mut count = 0;
-        for row in wrapper.rows.values() {
-            count += try!(self.insert_rows(&wrapper.object_name, &row)
+        for (id, row) in &wrapper.rows {
+            count += try!(self.insert_rows(&wrapper.object_name, &row, wrapper.modstamps.get(id))
                               .map_err(|err| err.to_string()));
         }
         Ok(count)
     }
 
+    //rows with local changes that were not pushed yet, keyed by sfid
+    fn get_pending_rows(&self, object_name: &str, sfids: &Vec<String>) -> Result<HashMap<String, PendingRow>, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let query = format!("SELECT id, sfid, _s_modified, _s_sf_modstamp, _s_state FROM {} WHERE sfid = ANY($1) AND (_s_modified IS NOT NULL OR _s_state IN ($2, $3))",
+                            quote_table(object_name));
+        let rows = conn.query(&query, &[sfids, &conflict::STATE_CONFLICT, &conflict::STATE_RESOLVED])
+            .map_err(|err| err.to_string())?;
+        let pending = rows.iter()
+            .map(|row| {
+                let pending_row = PendingRow {
+                    id: row.get(0),
+                    modified: row.get(2),
+                    sf_modstamp: row.get(3),
+                    state: row.get::<_, Option<String>>(4).unwrap_or(String::new()),
+                };
+                (row.get(1), pending_row)
+            })
+            .collect();
+        Ok(pending)
+    }
+
+    //decides whether a pulled record may overwrite a row with local changes
+    fn resolve_pulled_row(&self, object: &ObjectConfig, row: &Vec<ColumnValue>,
+                          modstamp: Option<&DateTime<Utc>>, local: &PendingRow) -> Result<bool, String> {
+        if local.state == conflict::STATE_CONFLICT || local.state == conflict::STATE_RESOLVED {
+            return Ok(false);
+        }
+        //the same salesforce version seen again through the sync overlap
+        if !conflict::sf_changed(local.sf_modstamp, modstamp) {
+            return Ok(false);
+        }
+        match conflict::resolve(object.conflict_policy, local.modified, modstamp) {
+            Resolution::TakeSalesforce => Ok(true),
+            Resolution::KeepDb => Ok(false),
+            Resolution::Park => {
+                let sf_version: serde_json::Map<String, serde_json::Value> = row.iter()
+                    .map(|column| (column.column.clone(), column.value.to_json()))
+                    .collect();
+                self.park_conflict(&object.name, &local.id, &serde_json::Value::Object(sf_version))?;
+                Ok(false)
+            }
+        }
+    }
+
+    //removes or flags rows whose salesforce records were deleted
+    pub fn delete_rows(&self, object_config: &ObjectConfig, sfids: &Vec<String>) -> Result<u64, String> {
+        if sfids.is_empty() {
+            return Ok(0);
+        }
+        let table_name = quote_table(&object_config.name);
+        let query = if object_config.hard_delete {
+            format!("DELETE FROM {} WHERE sfid = ANY($1)", table_name)
+        } else {
+            format!("UPDATE {} SET _s_state = 'DELETED', _s_deleted = timezone('utc', now()), _s_updated = timezone('utc', now()) WHERE sfid = ANY($1) AND _s_state <> 'DELETED'",
+                    table_name)
+        };
+        self.query_with_lock(&query, &[sfids], &object_config.name)
+    }
+
+    //removes rows regardless of the delete mode, used for records that left the filter
+    pub fn purge_rows(&self, object_config: &ObjectConfig, sfids: &Vec<String>) -> Result<u64, String> {
+        if sfids.is_empty() {
+            return Ok(0);
+        }
+        let query = format!("DELETE FROM {} WHERE sfid = ANY($1)", quote_table(&object_config.name));
+        self.query_with_lock(&query, &[sfids], &object_config.name)
+    }
+
     pub fn destroy(&self, id: i32, name: &String) {
-        let query = format!("DROP TABLE salesforce.{}", name.to_lowercase());
+        let query = format!("DROP TABLE {}", quote_table(name));
         let conn = self.pool.get().unwrap();
         let _result = conn.execute(query.as_str(), &[]).unwrap();
-        let query = format!("DELETE FROM config.objects where id = {}", id);
-        let _result = conn.execute(query.as_str(), &[]).unwrap();
+        let _result = conn.execute("DELETE FROM config.objects where id = $1", &[&id]).unwrap();
+        //pending entries of the dropped table would never find their config again
+        let _result = conn.execute("DELETE FROM config.outbox WHERE table_name = $1 AND done IS NULL",
+                                   &[&name.to_lowercase()]);
     }
 
     pub fn update_ids(&self,object_name: &String, ids_map: &HashMap<i32,String>) {
-        let mut id_str;
-        let mut sfid;
-        let table_name = format!("salesforce.{}",object_name);
-        for id in ids_map.keys(){
+        let table_name = quote_table(object_name);
+        for (id, sfid) in ids_map {
             let mut builder = UpdateQueryBuilder::new(&table_name);
-            id_str = id.to_string();
-            sfid = format!("'{}'", ids_map[id]);
-            builder.add_field("sfid", &sfid);
-            builder.add_and_where("id", &id_str , "=");
-            let _ = self.query_with_lock(&builder.build(), object_name);
+            builder.add_field("sfid", sfid);
+            builder.add_and_where("id", id, "=");
+            let _ = self.query_with_lock(&builder.build(), builder.params(), object_name);
         }
     }
 
     fn insert_rows(&self,
               object_name: &String,
-              row: &(Vec<String>, Vec<String>))
+              row: &Vec<ColumnValue>,
+              modstamp: Option<&DateTime<Utc>>)
               -> Result<u64, String> {
-        let row_values = row.1
-            .iter()
-            .map(|val| escape_single_quote(&val))
-            .collect::<Vec<String>>();
-        let query = format!("INSERT INTO salesforce.{} ({}) VALUES ({});",
-                            object_name,
-                            row.0.join(","),
-                            row_values.join(","));
+        let modstamp = modstamp.map(|modstamp| modstamp.naive_utc());
+        let mut builder = InsertQueryBuilder::new(&quote_table(object_name));
+        for column in row {
+            builder.add_field(&column.column, &column.value);
+        }
+        builder.add_field("_s_sf_modstamp", &modstamp);
+        let query = builder.build();
         //println!("{}", query);
-        self.query_with_lock(&query, &object_name)
+        self.query_with_lock(&query, builder.params(), &object_name)
     }
 
     fn update_rows(&self,
               id: &String,
               object_name: &String,
-              row: &(Vec<String>, Vec<String>))
+              row: &Vec<ColumnValue>,
+              modstamp: Option<&DateTime<Utc>>)
               -> Result<u64, String> {
-        let table_name = format!("salesforce.{}",object_name);
-        let mut builder = UpdateQueryBuilder::new(&table_name);
-        for i in 0..row.0.len() {
-            builder.add_field(&row.0[i], &row.1[i]);
+        let modstamp = modstamp.map(|modstamp| modstamp.naive_utc());
+        let unmodified: Option<NaiveDateTime> = None;
+        let mut builder = UpdateQueryBuilder::new(&quote_table(object_name));
+        for column in row {
+            builder.add_field(&column.column, &column.value);
         }
+        builder.add_field("_s_sf_modstamp", &modstamp);
+        builder.add_field("_s_modified", &unmodified);
+        //a record restored from the recycle bin is pulled again,
+        //a pending push that failed is replaced by the pulled values and no longer retried
+        builder.add_expression("_s_state", "CASE WHEN _s_state IN ('DELETED', 'ERROR') THEN 'OK' ELSE _s_state END");
+        builder.add_expression("_s_error", "CASE WHEN _s_state = 'ERROR' THEN NULL ELSE _s_error END");
+        builder.add_expression("_s_attempts", "CASE WHEN _s_state = 'ERROR' THEN 0 ELSE _s_attempts END");
+        builder.add_expression("_s_retry_at", "CASE WHEN _s_state = 'ERROR' THEN NULL ELSE _s_retry_at END");
+        builder.add_expression("_s_deleted", "NULL");
         builder.add_and_where("sfid", id, "=");
         let query = builder.build();
         // println!("{}", query);
-        self.query_with_lock(&query, &object_name)
+        self.query_with_lock(&query, builder.params(), &object_name)
     }
 
-    fn query_with_lock(&self, query: &String, object_name: &str) -> Result<u64, String>{
+    fn query_with_lock(&self, query: &str, params: &[&ToSql], object_name: &str) -> Result<u64, String>{
         //add channel lock flag here
-        let conn = self.pool.get().unwrap();
-        let _ = try!(conn.execute(&get_lock_query(object_name, true), &[])
-                                .map_err(|err| err.to_string()));
-        let result = try!(conn.execute(&query, &[])
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let lock_name = get_lock_name(object_name);
+        let _ = try!(conn.execute(get_lock_query(), &[&lock_name, &"lock"])
                                 .map_err(|err| err.to_string()));
-        let _ = try!(conn.execute(&get_lock_query(object_name, false), &[])
+        let result = conn.execute(query, params)
+                                .map_err(|err| err.to_string());
+        //release the lock even if the statement failed
+        let _ = try!(conn.execute(get_lock_query(), &[&lock_name, &""])
                                 .map_err(|err| err.to_string()));
+        let result = try!(result);
         Ok(result)
     }
 
+    pub fn claim_outbox(&self, limit: i64, lease_secs: i64) -> Result<Vec<OutboxEntry>, String> {
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        let rows = conn.query(CLAIM_QUERY, &[&limit, &(lease_secs as i32)])
+            .map_err(|err| err.to_string())?;
+        let mut entries: Vec<OutboxEntry> = rows.iter().map(|row| OutboxEntry::new(&row)).collect();
+        entries.sort_by_key(|entry| entry.id);
+        Ok(entries)
+    }
+
+    pub fn complete_outbox(&self, ids: &Vec<i64>) -> Result<u64, String> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let conn = self.pool.get().map_err(|err| err.to_string())?;
+        conn.execute("UPDATE config.outbox SET done = now() WHERE id = ANY($1)", &[ids])
+            .map_err(|err| err.to_string())
+    }
+
     pub fn get_notifications(&self) -> Vec<String>{
         let mut result = vec!();
         let conn = self.pool.get().unwrap();
@@ -261,6 +729,9 @@ impl Db {
 
 impl Clone for Db {
      fn clone(&self) -> Db {
-        Db::new(self.config)
+        Db {
+            pool: self.pool.clone(),
+            config: self.config
+        }
      }
 }
\ No newline at end of file
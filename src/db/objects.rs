@@ -1,5 +1,14 @@
 use serde_json;
+use chrono::prelude::*;
+use time::Duration;
+use postgres::rows::Row;
+use std::collections::HashMap;
+use config::{CompoundMode, FieldSelection, UnmatchedPolicy, SyncDirection, ConflictPolicy};
 use salesforce::objects::{Field, SObjectConfiguration};
+use salesforce::compound;
+
+//columns of config.objects read by ObjectConfig::from_row
+pub const OBJECT_COLUMNS: &str = "id, name, fields, COALESCE(watermark, last_sync_time), hard_delete, compound_mode, field_selection, record_filter, unmatched_policy, sync_direction, field_directions, conflict_policy, schedule_minutes";
 
 #[derive(Debug)]
 pub struct ObjectConfig {
@@ -7,29 +16,79 @@ pub struct ObjectConfig {
     pub name: String,
     pub count: u32,
     pub fields: Vec<Field>,
+    pub hard_delete: bool,
+    pub watermark: Option<NaiveDateTime>,
+    pub compound_mode: CompoundMode,
+    pub field_selection: FieldSelection,
+    pub filter: Option<String>,
+    pub unmatched_policy: UnmatchedPolicy,
+    pub direction: SyncDirection,
+    pub field_directions: HashMap<String, SyncDirection>,
+    pub conflict_policy: ConflictPolicy,
+    pub schedule_minutes: i32,
 }
 
 impl ObjectConfig {
-    pub fn new(id: i32, name: String, count: u32, fields: String) -> ObjectConfig {
+    pub fn from_row(row: &Row, count: u32) -> ObjectConfig {
+        let fields: String = row.get(2);
         let field_list: Vec<Field> = serde_json::from_str(fields.as_str()).unwrap();
+        let compound_mode: String = row.get(5);
+        let selection: String = row.get(6);
+        let unmatched_policy: String = row.get(8);
+        let direction: String = row.get(9);
+        let field_directions: String = row.get(10);
+        let conflict_policy: String = row.get(11);
         ObjectConfig {
-            id: id,
-            name: name,
-            count: count as u32,
+            id: row.get(0),
+            name: row.get(1),
+            count: count,
             fields: field_list,
+            hard_delete: row.get(4),
+            watermark: row.get(3),
+            compound_mode: CompoundMode::from_str(&compound_mode),
+            field_selection: serde_json::from_str(selection.as_str()).unwrap_or_default(),
+            filter: row.get(7),
+            unmatched_policy: UnmatchedPolicy::from_str(&unmatched_policy),
+            direction: SyncDirection::from_str(&direction),
+            field_directions: serde_json::from_str(field_directions.as_str()).unwrap_or_default(),
+            conflict_policy: ConflictPolicy::from_str(&conflict_policy),
+            schedule_minutes: row.get(12),
         }
     }
 
+    //start of the next incremental window, moved back by the overlap to absorb clock drift
+    pub fn get_sync_start(&self, overlap_secs: i64) -> DateTime<Utc> {
+        let watermark = match self.watermark {
+            Some(watermark) => DateTime::<Utc>::from_utc(watermark, Utc),
+            None => Utc::now(),
+        };
+        watermark - Duration::seconds(overlap_secs)
+    }
+
+    //blob fields of objects set up before they were left out are not queried either
     pub fn get_field_names(&self) -> Vec<String> {
-        self.fields.iter().map(|field| field.name.clone()).collect()
+        self.fields.iter().filter(|field| !field.is_blob()).map(|field| field.name.clone()).collect()
     }
 
+    //compound columns are not updateable themselves but are expanded into their components on push,
+    //blob columns never hold the content and would overwrite it with null
     pub fn get_db_field_names(&self) -> Vec<String> {
-        self.fields.iter()
-        .filter(|field| field.updateable && field.name != "Id")
+        compound::column_fields(&self.fields, self.compound_mode).into_iter()
+        .filter(|field| (field.updateable || compound::is_compound(field)) && field.name != "Id")
+        .filter(|field| !field.is_blob())
+        .filter(|field| self.get_field_direction(&field.name).pushes())
         .map(|field| field.name.to_lowercase())
         .collect()
     }
+
+    pub fn pulls_any(&self) -> bool {
+        self.fields.iter()
+            .any(|field| field.name != "Id" && self.get_field_direction(&field.name).pulls())
+    }
+
+    pub fn pushes_any(&self) -> bool {
+        !self.get_db_field_names().is_empty()
+    }
 }
 
 impl SObjectConfiguration for ObjectConfig {
@@ -40,4 +99,16 @@ impl SObjectConfiguration for ObjectConfig {
     fn get_fields(&self) -> &Vec<Field> {
         &self.fields
     }
+
+    fn get_compound_mode(&self) -> CompoundMode {
+        self.compound_mode
+    }
+
+    fn get_filter(&self) -> Option<&String> {
+        self.filter.as_ref()
+    }
+
+    fn get_field_direction(&self, field_name: &str) -> SyncDirection {
+        self.direction.for_field(&self.field_directions, field_name)
+    }
 }
@@ -0,0 +1,38 @@
+use postgres::rows::Row;
+
+pub const OP_DELETE: &str = "DELETE";
+
+//claims pending entries in insert order, expired leases of crashed workers are claimed again
+pub const CLAIM_QUERY: &str = "UPDATE config.outbox SET claimed_at = now() WHERE id IN (
+        SELECT id FROM config.outbox
+        WHERE done IS NULL AND (claimed_at IS NULL OR claimed_at < now() - $2::int * interval '1 second')
+        ORDER BY id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED)
+    RETURNING id, table_name, record_id, sfid, op";
+
+//change captured by the notify_change trigger
+#[derive(Debug)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub table_name: String,
+    pub record_id: i32,
+    pub sfid: Option<String>,
+    pub op: String,
+}
+
+impl OutboxEntry {
+    pub fn new(row: &Row) -> OutboxEntry {
+        OutboxEntry {
+            id: row.get(0),
+            table_name: row.get(1),
+            record_id: row.get(2),
+            sfid: row.get(3),
+            op: row.get(4),
+        }
+    }
+
+    pub fn is_delete(&self) -> bool {
+        self.op == OP_DELETE
+    }
+}
@@ -1,26 +1,30 @@
+use postgres::types::ToSql;
+
+pub const SCHEMA: &str = "salesforce";
+
 #[derive(Debug)]
-pub struct CreateQueryBuilder<'query> {
-    table_name: &'query str,
+pub struct CreateQueryBuilder {
+    table_name: String,
     fields: Vec<String>
 }
 
-impl<'query> CreateQueryBuilder<'query> {
+impl CreateQueryBuilder {
 
-    pub fn new(object_name: &str) -> CreateQueryBuilder {
+    pub fn new(table_name: &str) -> CreateQueryBuilder {
         CreateQueryBuilder {
-            table_name: object_name,
+            table_name: table_name.to_owned(),
             fields: Vec::new()
         }
     }
 
-    pub fn add_field(&mut self, name: &'query str, field_type: String) {
-        self.fields.push(format!("{} {}", name.to_lowercase(), field_type));
+    pub fn add_field(&mut self, name: &str, field_type: String) {
+        self.fields.push(format!("{} {}", quote_identifier(&name.to_lowercase()), field_type));
     }
 
     pub fn build(&self) -> String {
         let mut query = String::new();
         query.push_str("CREATE TABLE ");
-        query.push_str(self.table_name);
+        query.push_str(self.table_name.as_str());
         query.push_str("(");
         query.push_str(self.fields.join(",").as_str());
         query.push_str(")");
@@ -28,60 +32,146 @@ impl<'query> CreateQueryBuilder<'query> {
     }
 }
 
-#[derive(Debug)]
+pub struct InsertQueryBuilder<'insert> {
+    table_name: String,
+    fields: Vec<String>,
+    params: Vec<&'insert ToSql>
+}
+
+impl<'insert> InsertQueryBuilder<'insert> {
+
+    pub fn new(table_name: &str) -> InsertQueryBuilder<'insert> {
+        InsertQueryBuilder {
+            table_name: table_name.to_owned(),
+            fields: Vec::new(),
+            params: Vec::new()
+        }
+    }
+
+    pub fn add_field(&mut self, name: &str, value: &'insert ToSql) {
+        self.fields.push(quote_identifier(name));
+        self.params.push(value);
+    }
+
+    pub fn build(&self) -> String {
+        let placeholders: Vec<String> = (1..self.params.len() + 1)
+            .map(|i| format!("${}", i))
+            .collect();
+        format!("INSERT INTO {} ({}) VALUES ({})",
+                self.table_name,
+                self.fields.join(","),
+                placeholders.join(","))
+    }
+
+    pub fn params(&self) -> &[&'insert ToSql] {
+        &self.params
+    }
+}
+
 pub struct UpdateQueryBuilder<'update> {
-    table_name: &'update str,
+    table_name: String,
     fields: Vec<String>,
-    and_where: Vec<String>
+    and_where: Vec<String>,
+    params: Vec<&'update ToSql>
 }
 
 impl<'update> UpdateQueryBuilder<'update> {
-    
-    pub fn new(object_name: &str) -> UpdateQueryBuilder {
+
+    pub fn new(table_name: &str) -> UpdateQueryBuilder<'update> {
         UpdateQueryBuilder {
-            table_name: object_name,
+            table_name: table_name.to_owned(),
             fields: Vec::new(),
-            and_where: Vec::new()
+            and_where: Vec::new(),
+            params: Vec::new()
         }
     }
 
-    pub fn add_field(&mut self, name: &'update str, value: &'update str) {
-        self.fields.push(format!("{}={}", name, escape_single_quote(value)));
+    pub fn add_field(&mut self, name: &str, value: &'update ToSql) {
+        self.params.push(value);
+        self.fields.push(format!("{}=${}", quote_identifier(name), self.params.len()));
+    }
+
+    //the value is an sql expression, it may refer to the current values of the row
+    pub fn add_expression(&mut self, name: &str, expression: &str) {
+        self.fields.push(format!("{}={}", quote_identifier(name), expression));
     }
 
-    pub fn add_and_where(&mut self, name: &'update str, value: &'update str, operator: &str) {
-        self.and_where.push(format!("{} {} '{}'", name, operator, escape_single_quote(value)));
+    pub fn add_and_where(&mut self, name: &str, value: &'update ToSql, operator: &str) {
+        self.params.push(value);
+        self.and_where.push(format!("{} {} ${}", quote_identifier(name), operator, self.params.len()));
     }
 
     pub fn build(&self) -> String {
         let mut query = String::new();
         query.push_str("UPDATE ");
-        query.push_str(self.table_name);
+        query.push_str(self.table_name.as_str());
         query.push_str(" SET ");
         query.push_str(self.fields.join(",").as_str());
-        query.push_str(", _s_updated = NOW() ");
+        query.push_str(", _s_updated = timezone('utc', now()) ");
         if self.and_where.len() > 0 {
             query.push_str(" WHERE ");
             query.push_str(self.and_where.join(" AND ").as_str());
         }
-        
+
         query
     }
-}
 
-pub fn escape_single_quote(elem: &str) -> String {
-    if elem.starts_with("'") && elem.ends_with("'") {
-        let tmp = elem;
-        let tmp_slice = &tmp[1..elem.len() - 1];
-        let tmp_str = tmp_slice.to_string().replace("'", "''");
-        return format!("'{}'", tmp_str);
+    pub fn params(&self) -> &[&'update ToSql] {
+        &self.params
     }
-    return elem.to_owned();
 }
 
-pub fn get_lock_query(object_name: &str, lock: bool) -> String {
-    if lock {
-        return format!("SELECT set_config('salesforce.{}_lock','lock', false);", object_name);
+#[derive(Debug)]
+pub struct AlterQueryBuilder {
+    table_name: String,
+    actions: Vec<String>
+}
+
+impl AlterQueryBuilder {
+
+    pub fn new(table_name: &str) -> AlterQueryBuilder {
+        AlterQueryBuilder {
+            table_name: table_name.to_owned(),
+            actions: Vec::new()
+        }
+    }
+
+    //a field that comes back finds its kept column
+    pub fn add_column(&mut self, name: &str, field_type: &str) {
+        self.actions.push(format!("ADD COLUMN IF NOT EXISTS {} {}", quote_identifier(&name.to_lowercase()), field_type));
+    }
+
+    pub fn alter_column_type(&mut self, name: &str, field_type: &str) {
+        let column = quote_identifier(&name.to_lowercase());
+        self.actions.push(format!("ALTER COLUMN {} TYPE {} USING {}::{}", column, field_type, column, field_type));
+    }
+
+    pub fn drop_column(&mut self, name: &str) {
+        self.actions.push(format!("DROP COLUMN IF EXISTS {}", quote_identifier(&name.to_lowercase())));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
     }
-    format!("SELECT set_config('salesforce.{}_lock','', false);", object_name)   
-}
\ No newline at end of file
+
+    pub fn build(&self) -> String {
+        format!("ALTER TABLE {} {}", self.table_name, self.actions.join(", "))
+    }
+}
+
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace("\"", "\"\""))
+}
+
+//object tables are created unquoted, so their names are stored in lower case
+pub fn quote_table(object_name: &str) -> String {
+    format!("{}.{}", quote_identifier(SCHEMA), quote_identifier(&object_name.to_lowercase()))
+}
+
+pub fn get_lock_name(object_name: &str) -> String {
+    format!("{}.{}_lock", SCHEMA, object_name.to_lowercase())
+}
+
+pub fn get_lock_query() -> &'static str {
+    "SELECT set_config($1, $2, false)"
+}
@@ -1,6 +1,11 @@
-use chrono::{NaiveDateTime, NaiveDate};
+use chrono::{NaiveDateTime, NaiveDate, NaiveTime};
 use postgres::rows::Row;
-use postgres::types::{TEXT, INT4, INT8, VARCHAR, FLOAT8, FLOAT4, BOOL, TIMESTAMP,DATE};
+use postgres::types::{TEXT, INT4, INT8, VARCHAR, FLOAT8, FLOAT4, BOOL, TIMESTAMP,DATE,
+                      NUMERIC, TIME, BYTEA, TEXT_ARRAY, VARCHAR_ARRAY, JSON, JSONB};
+use db::value::Numeric;
+use db::objects::ObjectConfig;
+use salesforce::compound;
+use base64;
 use std::collections::HashMap;
 use serde_json;
 
@@ -12,12 +17,19 @@ enum Value {
     F32(f32),
     F64(f64),
     Bool(bool),
-    STR(String)
+    STR(String),
+    Json(serde_json::Value)
 }
 
+//fixed columns read before the object fields
+pub const RECORD_COLUMNS: &str = "id, sfid, _s_modified, _s_sf_modstamp, _s_state";
+
 pub struct Record {
     pub id: i32,
     pub sfid: Option<String>,
+    pub modified: Option<NaiveDateTime>,
+    pub sf_modstamp: Option<NaiveDateTime>,
+    pub state: Option<String>,
     data: HashMap<String,Option<Value>>
 }
 
@@ -27,6 +39,9 @@ impl Record {
         Record{
             id: row.get(0),
             sfid: row.get::<_,Option<String>>(1),
+            modified: row.get(2),
+            sf_modstamp: row.get(3),
+            state: row.get(4),
             data: Record::parse_data(row)
         }
     }
@@ -37,7 +52,7 @@ impl Record {
         let sfid = row.get::<_,Option<String>>(1);
         for column in row.columns().iter() {
            // println!("{:?}", column);
-            if idx < 2 {
+            if idx < 5 {
                 idx += 1;
                 continue;
             }
@@ -78,9 +93,34 @@ impl Record {
                         None => Some(Value::Bool(false)) 
                     }
                 },   
+                &NUMERIC => {
+                    match row.get::<_, Option<Numeric>>(idx) {
+                        Some(val) => Some(Value::F64(val.0)),
+                        None => None
+                    }
+                },
                 &TIMESTAMP =>  {
                     match row.get::<_, Option<NaiveDateTime>>(idx) {
-                        Some(d) => Some(Value::STR(d.to_string())),
+                        Some(d) => Some(Value::STR(d.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())),
+                        None => None
+                    }
+                },
+                &TIME => {
+                    match row.get::<_, Option<NaiveTime>>(idx) {
+                        Some(d) => Some(Value::STR(d.format("%H:%M:%S%.3fZ").to_string())),
+                        None => None
+                    }
+                },
+                &BYTEA => {
+                    match row.get::<_, Option<Vec<u8>>>(idx) {
+                        Some(d) => Some(Value::STR(base64::encode(&d))),
+                        None => None
+                    }
+                },
+                //multipicklist values are separated by semicolons
+                &TEXT_ARRAY | &VARCHAR_ARRAY => {
+                    match row.get::<_, Option<Vec<String>>>(idx) {
+                        Some(d) => Some(Value::STR(d.join(";"))),
                         None => None
                     }
                 },
@@ -90,6 +130,12 @@ impl Record {
                         None => None
                     }
                 },
+                &JSON | &JSONB => {
+                    match row.get::<_, Option<serde_json::Value>>(idx) {
+                        Some(d) => Some(Value::Json(d)),
+                        None => None
+                    }
+                },
                 _ => {
                     match row.get::<_, Option<String>>(idx) {
                         Some(d) => Some(Value::STR(d)),
@@ -105,10 +151,37 @@ impl Record {
         map
     }
 
+    //compound columns cannot be written, replace them with their writable components
+    pub fn expand_compounds(&mut self, config: &ObjectConfig) {
+        for field in config.fields.iter().filter(|field| compound::is_compound(field)) {
+            let value = match self.data.remove(&field.name.to_lowercase()) {
+                Some(Some(Value::Json(value))) => value,
+                Some(_) => serde_json::Value::Null,
+                None => continue,
+            };
+            for (name, component) in compound::expand(field, &config.fields, &value) {
+                let component = if component.is_null() { None } else { Some(Value::Json(component)) };
+                if Record::include_nulls(&self.sfid, &component) {
+                    self.data.insert(name.to_lowercase(), component);
+                }
+            }
+        }
+    }
+
     pub fn to_json(&self) -> String{
         serde_json::to_string(&self.data).unwrap()
     }
 
+    //record representation expected by the sObject Collections endpoints
+    pub fn to_collection_value(&self, object_type: &str) -> serde_json::Value {
+        let mut value = serde_json::to_value(&self.data).unwrap();
+        value["attributes"] = json!({"type": object_type});
+        if let Some(ref sfid) = self.sfid {
+            value["Id"] = json!(sfid);
+        }
+        value
+    }
+
     fn include_nulls(id: &Option<String>, value: &Option<Value>) -> bool {
         if id.is_none() && value.is_none(){
             return false;
@@ -0,0 +1,246 @@
+use std::error::Error;
+use std::f64;
+use std::iter::repeat;
+use chrono::NaiveDateTime;
+use postgres::types::{ToSql, FromSql, Type, IsNull, BOOL, INT2, INT4, INT8, FLOAT4, FLOAT8, NUMERIC,
+                      TIMESTAMP, TIMESTAMPTZ, DATE, TIME, BYTEA, TEXT_ARRAY, VARCHAR_ARRAY, JSON, JSONB};
+use salesforce::objects::FieldValue;
+
+fn mismatch(value: &FieldValue, ty: &Type) -> Box<Error + Sync + Send> {
+    format!("Cannot store {:?} in column of type {}", value, ty).into()
+}
+
+//values are converted according to the column they are written to
+impl ToSql for FieldValue {
+    fn to_sql(&self, ty: &Type, out: &mut Vec<u8>) -> Result<IsNull, Box<Error + Sync + Send>> {
+        match (self, ty) {
+            (&FieldValue::Null, _) => Ok(IsNull::Yes),
+            (&FieldValue::Bool(val), &BOOL) => val.to_sql(ty, out),
+            (&FieldValue::Integer(val), &INT2) => (val as i16).to_sql(ty, out),
+            (&FieldValue::Integer(val), &INT4) => (val as i32).to_sql(ty, out),
+            (&FieldValue::Integer(val), &INT8) => val.to_sql(ty, out),
+            (&FieldValue::Integer(val), &FLOAT4) => (val as f32).to_sql(ty, out),
+            (&FieldValue::Integer(val), &FLOAT8) => (val as f64).to_sql(ty, out),
+            (&FieldValue::Decimal(val), &INT2) => (val as i16).to_sql(ty, out),
+            (&FieldValue::Decimal(val), &INT4) => (val as i32).to_sql(ty, out),
+            (&FieldValue::Decimal(val), &INT8) => (val as i64).to_sql(ty, out),
+            (&FieldValue::Decimal(val), &FLOAT4) => (val as f32).to_sql(ty, out),
+            (&FieldValue::Decimal(val), &FLOAT8) => val.to_sql(ty, out),
+            (&FieldValue::Integer(val), &NUMERIC) => encode_numeric(&val.to_string(), out),
+            (&FieldValue::Decimal(val), &NUMERIC) => encode_numeric(&val.to_string(), out),
+            (&FieldValue::Time(ref val), &TIME) => val.to_sql(ty, out),
+            (&FieldValue::Binary(ref val), &BYTEA) => val.to_sql(ty, out),
+            (&FieldValue::List(ref val), &TEXT_ARRAY) |
+            (&FieldValue::List(ref val), &VARCHAR_ARRAY) => val.to_sql(ty, out),
+            (&FieldValue::Date(ref val), &DATE) => val.to_sql(ty, out),
+            (&FieldValue::Date(ref val), &TIMESTAMP) => val.and_hms(0, 0, 0).to_sql(ty, out),
+            (&FieldValue::DateTime(ref val), &TIMESTAMP) => {
+                let naive: NaiveDateTime = val.naive_utc();
+                naive.to_sql(ty, out)
+            },
+            (&FieldValue::DateTime(ref val), &TIMESTAMPTZ) => val.to_sql(ty, out),
+            (&FieldValue::DateTime(ref val), &DATE) => val.naive_utc().date().to_sql(ty, out),
+            (_, &JSON) | (_, &JSONB) => {
+                let json = match *self {
+                    FieldValue::Json(ref val) => val.to_string(),
+                    _ => json!(self.to_text()).to_string(),
+                };
+                if *ty == JSONB {
+                    out.push(1);
+                }
+                out.extend_from_slice(json.as_bytes());
+                Ok(IsNull::No)
+            },
+            (&FieldValue::Text(ref val), _) if !is_text(ty) => parse_text(self, val, ty, out),
+            (_, _) if is_text(ty) => self.to_text().unwrap_or(String::new()).to_sql(ty, out),
+            _ => Err(mismatch(self, ty)),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    fn to_sql_checked(&self, ty: &Type, out: &mut Vec<u8>) -> Result<IsNull, Box<Error + Sync + Send>> {
+        self.to_sql(ty, out)
+    }
+}
+
+fn is_text(ty: &Type) -> bool {
+    <String as ToSql>::accepts(ty)
+}
+
+//text that did not match its describe type, e.g. a string stored into a numeric column
+fn parse_text(value: &FieldValue, val: &str, ty: &Type, out: &mut Vec<u8>)
+    -> Result<IsNull, Box<Error + Sync + Send>> {
+    match *ty {
+        BOOL => val.parse::<bool>()?.to_sql(ty, out),
+        INT2 | INT4 | INT8 => FieldValue::Integer(val.parse::<i64>()?).to_sql(ty, out),
+        FLOAT4 | FLOAT8 => FieldValue::Decimal(val.parse::<f64>()?).to_sql(ty, out),
+        NUMERIC => encode_numeric(val, out),
+        TEXT_ARRAY | VARCHAR_ARRAY => {
+            let list: Vec<String> = val.split(';').map(|item| item.to_owned()).collect();
+            list.to_sql(ty, out)
+        },
+        _ => Err(mismatch(value, ty)),
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, val: u16) {
+    out.push((val >> 8) as u8);
+    out.push(val as u8);
+}
+
+//sign, integer and fraction digits of a decimal string, an exponent moves the decimal point
+fn split_numeric(val: &str) -> Option<(bool, String, String)> {
+    let negative = val.starts_with('-');
+    let unsigned = if negative || val.starts_with('+') { &val[1..] } else { val };
+    let mut parts = unsigned.splitn(2, |c| c == 'e' || c == 'E');
+    let mantissa = parts.next().unwrap_or("");
+    let exponent = match parts.next() {
+        Some(exponent) => exponent.parse::<i64>().ok()?,
+        None => 0,
+    };
+    let mut parts = mantissa.splitn(2, '.');
+    let int_part = parts.next().unwrap_or("");
+    let frac_part = parts.next().unwrap_or("");
+    let digits: String = int_part.chars().chain(frac_part.chars()).collect();
+    //bounds the zeros an exponent adds, postgres keeps at most 16383 fraction digits
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(10)) || exponent.abs() > 16383 {
+        return None;
+    }
+    let point = int_part.len() as i64 + exponent;
+    let (int_digits, frac_digits): (String, String) = if point <= 0 {
+        (String::new(), repeat('0').take(-point as usize).chain(digits.chars()).collect())
+    } else if point as usize >= digits.len() {
+        (digits.chars().chain(repeat('0').take(point as usize - digits.len())).collect(), String::new())
+    } else {
+        let (int_digits, frac_digits) = digits.split_at(point as usize);
+        (int_digits.to_owned(), frac_digits.to_owned())
+    };
+    Some((negative, int_digits.chars().skip_while(|c| *c == '0').collect(), frac_digits))
+}
+
+//binary NUMERIC format: ndigits, weight, sign, dscale followed by base 10000 digits
+fn encode_numeric(val: &str, out: &mut Vec<u8>) -> Result<IsNull, Box<Error + Sync + Send>> {
+    let (negative, int_part, frac_part) = split_numeric(val)
+        .ok_or(format!("Invalid numeric value: {}", val))?;
+    let int_pad = (4 - int_part.len() % 4) % 4;
+    let frac_pad = (4 - frac_part.len() % 4) % 4;
+    let padded: String = repeat('0').take(int_pad)
+        .chain(int_part.chars())
+        .chain(frac_part.chars())
+        .chain(repeat('0').take(frac_pad))
+        .collect();
+    let mut digits: Vec<u16> = padded.as_bytes()
+        .chunks(4)
+        .map(|chunk| chunk.iter().fold(0, |acc, digit| acc * 10 + (digit - b'0') as u16))
+        .collect();
+    let mut weight = ((int_part.len() + int_pad) / 4) as i16 - 1;
+    while digits.first() == Some(&0) {
+        digits.remove(0);
+        weight -= 1;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    if digits.is_empty() {
+        weight = 0;
+    }
+    push_u16(out, digits.len() as u16);
+    push_u16(out, weight as u16);
+    push_u16(out, if negative && !digits.is_empty() { 0x4000 } else { 0 });
+    push_u16(out, frac_part.len() as u16);
+    for digit in digits {
+        push_u16(out, digit);
+    }
+    Ok(IsNull::No)
+}
+
+//reads NUMERIC columns, precision beyond f64 is lost
+pub struct Numeric(pub f64);
+
+impl FromSql for Numeric {
+    fn from_sql(_ty: &Type, raw: &[u8]) -> Result<Numeric, Box<Error + Sync + Send>> {
+        let read = |i: usize| -> Result<u16, Box<Error + Sync + Send>> {
+            match (raw.get(i), raw.get(i + 1)) {
+                (Some(high), Some(low)) => Ok((*high as u16) << 8 | *low as u16),
+                _ => Err("Invalid numeric value".into()),
+            }
+        };
+        let ndigits = read(0)? as usize;
+        let weight = read(2)? as i16 as i32;
+        let sign = read(4)?;
+        if sign == 0xC000 {
+            return Ok(Numeric(f64::NAN));
+        }
+        let mut value = 0f64;
+        for i in 0..ndigits {
+            value += read(8 + i * 2)? as f64 * 10000f64.powi(weight - i as i32);
+        }
+        if sign == 0x4000 {
+            value = -value;
+        }
+        Ok(Numeric(value))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == NUMERIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(val: &str) -> f64 {
+        let mut out = vec!();
+        encode_numeric(val, &mut out).unwrap();
+        Numeric::from_sql(&NUMERIC, &out).unwrap().0
+    }
+
+    fn assert_close(val: &str, expected: f64) {
+        let actual = round_trip(val);
+        assert!((actual - expected).abs() <= expected.abs() * 1e-12, "{} -> {}", val, actual);
+    }
+
+    #[test]
+    fn encodes_base_10000_digits() {
+        let mut out = vec!();
+        encode_numeric("123.45", &mut out).unwrap();
+        //ndigits 2, weight 0, positive, dscale 2, digits 123 and 4500
+        assert_eq!(out, vec!(0, 2, 0, 0, 0, 0, 0, 2, 0, 123, 0x11, 0x94));
+    }
+
+    #[test]
+    fn round_trips_decimals() {
+        assert_close("123.45", 123.45);
+        assert_close("-0.001", -0.001);
+        assert_close("10000", 10000.0);
+        assert_close("+42", 42.0);
+        assert_close("0", 0.0);
+    }
+
+    #[test]
+    fn round_trips_exponents() {
+        assert_close("1E+21", 1e21);
+        assert_close("1.0E-7", 1e-7);
+        assert_close("-2.5e3", -2500.0);
+        assert_close("12.345E1", 123.45);
+    }
+
+    #[test]
+    fn keeps_the_scale_of_shifted_fractions() {
+        assert_eq!(split_numeric("1.0E-7"), Some((false, String::new(), "00000010".to_owned())));
+        assert_eq!(split_numeric("1E+21"), Some((false, format!("1{}", "0".repeat(21)), String::new())));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut out = vec!();
+        assert!(encode_numeric("abc", &mut out).is_err());
+        assert!(encode_numeric("1e", &mut out).is_err());
+        assert!(encode_numeric("", &mut out).is_err());
+        assert!(encode_numeric("1.2.3", &mut out).is_err());
+    }
+}
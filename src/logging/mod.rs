@@ -0,0 +1,228 @@
+pub mod sinks;
+
+use chrono::prelude::*;
+use serde_json::Value;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use config::{LogConfig, LogLevel};
+use logging::sinks::Sink;
+
+//set while the interactive menu has the console, events are kept until it is released
+static CONSOLE_PAUSED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref SENDER: Mutex<Option<EventSender>> = Mutex::new(None);
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub time: DateTime<Utc>,
+    pub level: LogLevel,
+    pub message: String,
+    pub object: Option<String>,
+    pub executer: Option<String>,
+    pub record_id: Option<i32>,
+    pub count: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl Event {
+    pub fn new(level: LogLevel, message: &str) -> Event {
+        Event {
+            time: Utc::now(),
+            level: level,
+            message: message.to_owned(),
+            object: None,
+            executer: None,
+            record_id: None,
+            count: None,
+            error: None,
+        }
+    }
+
+    pub fn debug(message: &str) -> Event {
+        Event::new(LogLevel::Debug, message)
+    }
+
+    pub fn info(message: &str) -> Event {
+        Event::new(LogLevel::Info, message)
+    }
+
+    pub fn warn(message: &str) -> Event {
+        Event::new(LogLevel::Warn, message)
+    }
+
+    pub fn error(message: &str) -> Event {
+        Event::new(LogLevel::Error, message)
+    }
+
+    pub fn with_object(mut self, object: &str) -> Event {
+        self.object = Some(object.to_owned());
+        self
+    }
+
+    pub fn with_executer(mut self, executer: &str) -> Event {
+        self.executer = Some(executer.to_owned());
+        self
+    }
+
+    pub fn with_record(mut self, record_id: i32) -> Event {
+        self.record_id = Some(record_id);
+        self
+    }
+
+    pub fn with_count(mut self, count: u64) -> Event {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn with_error(mut self, error: &str) -> Event {
+        self.error = Some(error.to_owned());
+        self
+    }
+
+    //sends the event to the global logger
+    pub fn emit(self) {
+        sender().send(self);
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "time": self.time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            "level": self.level.as_str(),
+            "message": self.message,
+            "object": self.object,
+            "executer": self.executer,
+            "record_id": self.record_id,
+            "count": self.count,
+            "error": self.error,
+        })
+    }
+}
+
+//2018-05-01 12:00:00 INFO [sf_executer] Account #12 Synched rows (count: 5): error
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {:5}", self.time.format("%Y-%m-%d %H:%M:%S"), self.level.as_str().to_uppercase())?;
+        if let Some(ref executer) = self.executer {
+            write!(f, " [{}]", executer)?;
+        }
+        if let Some(ref object) = self.object {
+            write!(f, " {}", object)?;
+        }
+        if let Some(record_id) = self.record_id {
+            write!(f, " #{}", record_id)?;
+        }
+        write!(f, " {}", self.message)?;
+        if let Some(count) = self.count {
+            write!(f, " (count: {})", count)?;
+        }
+        if let Some(ref error) = self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+enum Message {
+    Event(Event),
+    Flush(Sender<()>),
+}
+
+//typed replacement of the string channel, tags events with the executer it belongs to
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<Message>,
+    executer: Option<String>,
+}
+
+impl EventSender {
+    pub fn send(&self, event: Event) {
+        let event = match (event.executer.is_none(), self.executer.as_ref()) {
+            (true, Some(executer)) => event.with_executer(executer),
+            _ => event,
+        };
+        let _ = self.sender.send(Message::Event(event));
+    }
+
+    pub fn for_executer(&self, executer: &str) -> EventSender {
+        EventSender {
+            sender: self.sender.clone(),
+            executer: Some(executer.to_owned()),
+        }
+    }
+
+    //blocks until every event sent before was written
+    pub fn flush(&self) {
+        let (done_send, done_recv) = channel();
+        if self.sender.send(Message::Flush(done_send)).is_ok() {
+            let _ = done_recv.recv();
+        }
+    }
+}
+
+//starts the writer thread, json_console prints console events as json lines
+pub fn init(config: &LogConfig, json_console: bool) -> Result<(), String> {
+    let sinks = config.sinks.iter()
+        .map(|sink| sinks::from_config(sink, json_console))
+        .collect::<Result<Vec<Box<Sink>>, String>>()?;
+    *SENDER.lock().unwrap() = Some(start(config.level, sinks));
+    Ok(())
+}
+
+//events sent before init go to the console
+pub fn sender() -> EventSender {
+    SENDER.lock()
+        .unwrap()
+        .get_or_insert_with(|| start(LogLevel::Info, vec!(Box::new(sinks::ConsoleSink::new(false)))))
+        .clone()
+}
+
+pub fn flush() {
+    sender().flush();
+}
+
+pub fn pause_console() {
+    CONSOLE_PAUSED.store(true, Ordering::SeqCst);
+}
+
+//prints the events kept while the console was paused
+pub fn resume_console() {
+    CONSOLE_PAUSED.store(false, Ordering::SeqCst);
+    flush();
+}
+
+pub fn is_console_paused() -> bool {
+    CONSOLE_PAUSED.load(Ordering::SeqCst)
+}
+
+fn start(level: LogLevel, mut sinks: Vec<Box<Sink>>) -> EventSender {
+    let (send, recv) = channel::<Message>();
+    thread::spawn(move || {
+        for message in recv.iter() {
+            match message {
+                Message::Event(event) => {
+                    if event.level < level {
+                        continue;
+                    }
+                    for sink in sinks.iter_mut() {
+                        sink.write(&event);
+                    }
+                },
+                Message::Flush(done) => {
+                    for sink in sinks.iter_mut() {
+                        sink.flush();
+                    }
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    EventSender {
+        sender: send,
+        executer: None,
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use config::SinkConfig;
+use logging::{self, Event};
+
+//events kept while the console is paused, older ones are dropped
+const BACKLOG_SIZE: usize = 1000;
+
+pub trait Sink: Send {
+    fn write(&mut self, event: &Event);
+    fn flush(&mut self) {}
+}
+
+pub fn from_config(config: &SinkConfig, json_console: bool) -> Result<Box<Sink>, String> {
+    match *config {
+        SinkConfig::Console => Ok(Box::new(ConsoleSink::new(json_console))),
+        SinkConfig::File { ref path, max_bytes, keep } => {
+            Ok(Box::new(FileSink::open(path, max_bytes, keep, false)?))
+        },
+        SinkConfig::JsonLines { path: Some(ref path), max_bytes, keep } => {
+            Ok(Box::new(FileSink::open(path, max_bytes, keep, true)?))
+        },
+        SinkConfig::JsonLines { path: None, .. } => Ok(Box::new(ConsoleSink::new(true))),
+    }
+}
+
+fn format_event(event: &Event, json: bool) -> String {
+    if json {
+        event.to_json().to_string()
+    } else {
+        event.to_string()
+    }
+}
+
+//events go to stderr, stdout is kept for the output of the command
+pub struct ConsoleSink {
+    json: bool,
+    backlog: VecDeque<Event>,
+}
+
+impl ConsoleSink {
+    pub fn new(json: bool) -> ConsoleSink {
+        ConsoleSink {
+            json: json,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    fn print_backlog(&mut self) {
+        while let Some(event) = self.backlog.pop_front() {
+            eprintln!("{}", format_event(&event, self.json));
+        }
+    }
+}
+
+impl Sink for ConsoleSink {
+    fn write(&mut self, event: &Event) {
+        if logging::is_console_paused() {
+            if self.backlog.len() >= BACKLOG_SIZE {
+                self.backlog.pop_front();
+            }
+            self.backlog.push_back(event.clone());
+            return;
+        }
+        self.print_backlog();
+        eprintln!("{}", format_event(event, self.json));
+    }
+
+    fn flush(&mut self) {
+        if !logging::is_console_paused() {
+            self.print_backlog();
+        }
+        let _ = io::stderr().flush();
+    }
+}
+
+pub struct FileSink {
+    path: String,
+    max_bytes: u64,
+    keep: u32,
+    json: bool,
+    file: File,
+    size: u64,
+}
+
+impl FileSink {
+    pub fn open(path: &str, max_bytes: u64, keep: u32, json: bool) -> Result<FileSink, String> {
+        let file = open_append(path)?;
+        let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        Ok(FileSink {
+            path: path.to_owned(),
+            max_bytes: max_bytes,
+            keep: keep,
+            json: json,
+            file: file,
+            size: size,
+        })
+    }
+
+    //log -> log.1 -> log.2 ..., the oldest file is overwritten
+    fn rotate(&mut self) -> Result<(), String> {
+        for i in (1..self.keep).rev() {
+            let from = format!("{}.{}", self.path, i);
+            if fs::metadata(&from).is_ok() {
+                fs::rename(&from, format!("{}.{}", self.path, i + 1)).map_err(|err| err.to_string())?;
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, format!("{}.1", self.path)).map_err(|err| err.to_string())?;
+        } else {
+            fs::remove_file(&self.path).map_err(|err| err.to_string())?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Sink for FileSink {
+    fn write(&mut self, event: &Event) {
+        let line = format!("{}\n", format_event(event, self.json));
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            if let Err(err) = self.rotate() {
+                eprintln!("Could not rotate {}: {}", self.path, err);
+            }
+        }
+        match self.file.write_all(line.as_bytes()) {
+            Ok(_) => self.size += line.len() as u64,
+            Err(err) => eprintln!("Could not write to {}: {}", self.path, err),
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+fn open_append(path: &str) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Could not open log file {}: {}", path, err))
+}
@@ -1,5 +1,6 @@
 extern crate reqwest;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 extern crate chrono;
 extern crate time;
@@ -7,6 +8,11 @@ extern crate postgres;
 extern crate r2d2;
 extern crate r2d2_postgres;
 extern crate fallible_iterator;
+extern crate openssl;
+extern crate base64;
+extern crate clap;
+extern crate libc;
+extern crate rand;
 
 #[macro_use]
 extern crate lazy_static;
@@ -18,14 +24,11 @@ mod salesforce;
 mod config;
 mod sync;
 mod db;
-use config::Config;
-use sync::Sync;
-
-lazy_static! {
-    static ref CONF:Config = Config::new("config/config.json").unwrap();
-}
+mod cli;
+mod logging;
+mod metrics;
+use std::process;
 
 fn main() {
-    let mut syncher = Sync::new(&CONF);
-    syncher.run();
+    process::exit(cli::run());
 }
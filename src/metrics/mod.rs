@@ -0,0 +1,106 @@
+pub mod server;
+
+use chrono::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const RECORDS_PULLED: &str = "crm_sync_records_pulled_total";
+pub const RECORDS_PUSHED: &str = "crm_sync_records_pushed_total";
+pub const ERRORS: &str = "crm_sync_errors_total";
+pub const API_CALLS: &str = "crm_sync_salesforce_api_calls_total";
+pub const CYCLE_DURATION: &str = "crm_sync_cycle_duration_seconds";
+pub const WATERMARK_AGE: &str = "crm_sync_watermark_age_seconds";
+pub const OUTBOX_DEPTH: &str = "crm_sync_outbox_depth";
+pub const ERROR_ROWS: &str = "crm_sync_error_rows";
+pub const API_USAGE: &str = "crm_sync_salesforce_api_usage";
+pub const API_LIMIT: &str = "crm_sync_salesforce_api_limit";
+
+//object label of errors that are not tied to an object
+pub const GLOBAL_OBJECT: &str = "_global";
+
+//name, type and help text in the order they are exposed
+const METRICS: [(&str, &str, &str); 10] = [
+    (RECORDS_PULLED, "counter", "Records pulled from Salesforce"),
+    (RECORDS_PUSHED, "counter", "Records pushed to Salesforce"),
+    (ERRORS, "counter", "Errors while syncing"),
+    (API_CALLS, "counter", "Requests sent to the Salesforce API"),
+    (CYCLE_DURATION, "gauge", "Duration of the last cycle of an executer"),
+    (WATERMARK_AGE, "gauge", "Seconds since the watermark of an object"),
+    (OUTBOX_DEPTH, "gauge", "Outbox entries not yet pushed"),
+    (ERROR_ROWS, "gauge", "Rows in ERROR state waiting for a retry"),
+    (API_USAGE, "gauge", "Daily api requests used by the org as last reported by Salesforce"),
+    (API_LIMIT, "gauge", "Daily api request limit of the org"),
+];
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Default::default());
+}
+
+#[derive(Default)]
+struct Registry {
+    //keyed by metric name and rendered labels
+    values: BTreeMap<(&'static str, String), f64>,
+    //the age is computed on every scrape so it keeps growing when an object stops syncing
+    watermarks: BTreeMap<String, DateTime<Utc>>,
+}
+
+pub fn inc_counter(name: &'static str, labels: &[(&str, &str)], value: u64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    *registry.values.entry((name, format_labels(labels))).or_insert(0.0) += value as f64;
+}
+
+pub fn set_gauge(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.values.insert((name, format_labels(labels)), value);
+}
+
+pub fn count_error(executer: &str, object_name: &str) {
+    inc_counter(ERRORS, &[("executer", executer), ("object", object_name)], 1);
+}
+
+pub fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+pub fn set_watermark(object_name: &str, watermark: DateTime<Utc>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.watermarks.insert(object_name.to_owned(), watermark);
+}
+
+//prometheus text format
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let now = Utc::now();
+    let mut output = String::new();
+    for &(name, metric_type, help) in METRICS.iter() {
+        output.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, metric_type));
+        if name == WATERMARK_AGE {
+            for (object_name, watermark) in registry.watermarks.iter() {
+                let age = now.signed_duration_since(*watermark).num_milliseconds() as f64 / 1000.0;
+                output.push_str(&format!("{}{} {}\n", name, format_labels(&[("object", object_name)]), age));
+            }
+            continue;
+        }
+        let values = registry.values.range((name, String::new())..)
+            .take_while(|&(&(key, _), _)| key == name);
+        for (&(_, ref labels), value) in values {
+            output.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    }
+    output
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels.iter()
+        .map(|&(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
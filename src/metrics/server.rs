@@ -0,0 +1,50 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use logging::Event;
+use metrics;
+
+const READ_TIMEOUT_SECS: u64 = 5;
+
+//serves GET /metrics on a background thread, one request per connection
+pub fn start(address: &str) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| format!("Could not bind metrics endpoint to {}: {}", address, err))?;
+    Event::info(&format!("Metrics available on http://{}/metrics", address)).emit();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(handle);
+            if let Err(err) = result {
+                Event::warn("Metrics request failed").with_error(&err.to_string()).emit();
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(&stream);
+        reader.read_line(&mut request_line)?;
+        //the headers are not needed but have to be read before answering
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next();
+    let path = parts.next().map(|path| path.split('?').next().unwrap_or(path));
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics::render()),
+        _ => ("404 Not Found", "Not Found\n".to_owned()),
+    };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, body.len(), body)?;
+    stream.flush()
+}
@@ -0,0 +1,124 @@
+use std::io::prelude::*;
+use std::fs::File;
+use config::{SalesforceConfig, AuthConfig};
+use chrono::prelude::*;
+use base64;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::hash::MessageDigest;
+
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+//salesforce accepts assertions valid for at most 3 minutes
+const JWT_LIFETIME_SECS: i64 = 180;
+
+pub trait AuthStrategy: Send + Sync {
+    fn name(&self) -> &str;
+    //form parameters posted to the token endpoint
+    fn token_params(&self) -> Result<Vec<(String, String)>, String>;
+}
+
+pub fn from_config(config: &'static SalesforceConfig) -> Box<AuthStrategy> {
+    match config.get_auth() {
+        AuthConfig::Password { username, password, sec_token } => Box::new(PasswordAuth {
+            config: config,
+            username: username,
+            password: format!("{}{}", password, sec_token),
+        }),
+        AuthConfig::JwtBearer { username, private_key_file, audience } => Box::new(JwtBearerAuth {
+            config: config,
+            username: username,
+            private_key_file: private_key_file,
+            audience: audience.unwrap_or(login_host(&config.uri)),
+        }),
+        AuthConfig::ClientCredentials => Box::new(ClientCredentialsAuth { config: config }),
+    }
+}
+
+//https://login.salesforce.com/services/oauth2/token -> https://login.salesforce.com
+fn login_host(uri: &str) -> String {
+    uri.split("/services/").next().unwrap_or(uri).to_owned()
+}
+
+pub struct PasswordAuth {
+    config: &'static SalesforceConfig,
+    username: String,
+    password: String,
+}
+
+impl AuthStrategy for PasswordAuth {
+    fn name(&self) -> &str {
+        "password"
+    }
+
+    fn token_params(&self) -> Result<Vec<(String, String)>, String> {
+        Ok(vec!(
+            ("grant_type".to_owned(), "password".to_owned()),
+            ("client_id".to_owned(), self.config.client_id.clone()),
+            ("client_secret".to_owned(), self.config.client_secret.clone()),
+            ("username".to_owned(), self.username.clone()),
+            ("password".to_owned(), self.password.clone()),
+        ))
+    }
+}
+
+pub struct JwtBearerAuth {
+    config: &'static SalesforceConfig,
+    username: String,
+    private_key_file: String,
+    audience: String,
+}
+
+impl JwtBearerAuth {
+    fn build_assertion(&self) -> Result<String, String> {
+        let header = json!({"alg": "RS256"});
+        let claims = json!({
+            "iss": self.config.client_id,
+            "sub": self.username,
+            "aud": self.audience,
+            "exp": Utc::now().timestamp() + JWT_LIFETIME_SECS,
+        });
+        let signing_input = format!("{}.{}",
+                                    base64::encode_config(&header.to_string(), base64::URL_SAFE_NO_PAD),
+                                    base64::encode_config(&claims.to_string(), base64::URL_SAFE_NO_PAD));
+        let mut pem = Vec::new();
+        File::open(&self.private_key_file)
+            .and_then(|mut file| file.read_to_end(&mut pem))
+            .map_err(|err| format!("Cannot read private key {}: {}", self.private_key_file, err))?;
+        let key = PKey::private_key_from_pem(&pem).map_err(|err| err.to_string())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|err| err.to_string())?;
+        signer.update(signing_input.as_bytes()).map_err(|err| err.to_string())?;
+        let signature = signer.sign_to_vec().map_err(|err| err.to_string())?;
+        Ok(format!("{}.{}", signing_input, base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)))
+    }
+}
+
+impl AuthStrategy for JwtBearerAuth {
+    fn name(&self) -> &str {
+        "jwt_bearer"
+    }
+
+    fn token_params(&self) -> Result<Vec<(String, String)>, String> {
+        Ok(vec!(
+            ("grant_type".to_owned(), JWT_BEARER_GRANT.to_owned()),
+            ("assertion".to_owned(), self.build_assertion()?),
+        ))
+    }
+}
+
+pub struct ClientCredentialsAuth {
+    config: &'static SalesforceConfig,
+}
+
+impl AuthStrategy for ClientCredentialsAuth {
+    fn name(&self) -> &str {
+        "client_credentials"
+    }
+
+    fn token_params(&self) -> Result<Vec<(String, String)>, String> {
+        Ok(vec!(
+            ("grant_type".to_owned(), "client_credentials".to_owned()),
+            ("client_id".to_owned(), self.config.client_id.clone()),
+            ("client_secret".to_owned(), self.config.client_secret.clone()),
+        ))
+    }
+}
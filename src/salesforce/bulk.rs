@@ -0,0 +1,148 @@
+use serde_json::{Map, Value};
+use salesforce::objects::Field;
+
+pub const STATE_JOB_COMPLETE: &str = "JobComplete";
+pub const STATE_FAILED: &str = "Failed";
+pub const STATE_ABORTED: &str = "Aborted";
+pub const LOCATOR_HEADER: &str = "Sforce-Locator";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BulkQueryJob {
+    pub id: String,
+    pub state: String,
+    #[serde(rename = "errorMessage", default)]
+    pub error_message: Option<String>,
+    #[serde(rename = "numberRecordsProcessed", default)]
+    pub records_processed: u64,
+}
+
+impl BulkQueryJob {
+    pub fn is_finished(&self) -> bool {
+        self.state == STATE_JOB_COMPLETE || self.state == STATE_FAILED || self.state == STATE_ABORTED
+    }
+}
+
+//converts a bulk result page into the same shape as a REST query result
+pub fn csv_to_query_result(csv: &str, fields: &Vec<Field>, locator: &Option<String>) -> Value {
+    let mut lines = parse_csv(csv).into_iter();
+    let header = lines.next().unwrap_or(vec!());
+    let records: Vec<Value> = lines
+        .map(|line| {
+            let mut record = Map::new();
+            for (name, value) in header.iter().zip(line.into_iter()) {
+                //bulk results do not distinguish null from empty strings
+                let value = if value.is_empty() {
+                    Value::Null
+                } else {
+                    Value::String(value)
+                };
+                record.insert(field_name(fields, name), value);
+            }
+            Value::Object(record)
+        })
+        .collect();
+    let next = match *locator {
+        Some(ref locator) if locator != "null" => Some(locator.clone()),
+        _ => None,
+    };
+    json!({
+        "records": records,
+        "done": next.is_none(),
+        "nextRecordsUrl": next.unwrap_or(String::new()),
+    })
+}
+
+//csv headers are not guaranteed to use the describe casing
+fn field_name(fields: &Vec<Field>, name: &str) -> String {
+    fields.iter()
+        .find(|field| field.name.eq_ignore_ascii_case(name))
+        .map(|field| field.name.clone())
+        .unwrap_or(name.to_owned())
+}
+
+//RFC 4180 parser, salesforce quotes every value and may embed line breaks
+pub fn parse_csv(input: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    value.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                _ => value.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            ',' => row.push(value.split_off(0)),
+            '\r' => {}
+            '\n' => {
+                row.push(value.split_off(0));
+                rows.push(row.split_off(0));
+            }
+            _ => value.push(c),
+        }
+    }
+    if !value.is_empty() || !row.is_empty() {
+        row.push(value);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_commas() {
+        let rows = parse_csv("\"Id\",\"Name\"\n\"001\",\"Smith, John\"\n");
+        assert_eq!(rows, vec!(vec!("Id", "Name"), vec!("001", "Smith, John")));
+    }
+
+    #[test]
+    fn parses_escaped_quotes() {
+        let rows = parse_csv("\"Name\"\n\"The \"\"Best\"\" Company\"\n");
+        assert_eq!(rows, vec!(vec!("Name"), vec!("The \"Best\" Company")));
+    }
+
+    #[test]
+    fn parses_embedded_newlines() {
+        let rows = parse_csv("\"Id\",\"Street\"\n\"001\",\"Line 1\nLine 2\r\nLine 3\"\n");
+        assert_eq!(rows, vec!(vec!("Id", "Street"), vec!("001", "Line 1\nLine 2\r\nLine 3")));
+    }
+
+    #[test]
+    fn parses_crlf_line_endings() {
+        let rows = parse_csv("\"Id\",\"Name\"\r\n\"001\",\"Acme\"\r\n\"002\",\"\"\r\n");
+        assert_eq!(rows, vec!(vec!("Id", "Name"), vec!("001", "Acme"), vec!("002", "")));
+    }
+
+    #[test]
+    fn parses_last_line_without_newline() {
+        let rows = parse_csv("\"Id\",\"Name\"\n\"001\",\"Acme\"");
+        assert_eq!(rows, vec!(vec!("Id", "Name"), vec!("001", "Acme")));
+    }
+
+    #[test]
+    fn empty_values_become_null() {
+        let result = csv_to_query_result("\"Id\",\"Name\"\n\"001\",\"\"\n", &vec!(), &Some("null".to_owned()));
+        assert_eq!(result["records"][0]["Id"], json!("001"));
+        assert_eq!(result["records"][0]["Name"], Value::Null);
+        assert_eq!(result["done"], json!(true));
+    }
+
+    #[test]
+    fn locator_leads_to_next_page() {
+        let result = csv_to_query_result("\"Id\"\n\"001\"\n", &vec!(), &Some("MTAwMDA".to_owned()));
+        assert_eq!(result["done"], json!(false));
+        assert_eq!(result["nextRecordsUrl"], json!("MTAwMDA"));
+    }
+}
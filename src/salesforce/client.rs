@@ -1,9 +1,22 @@
 use std::collections::HashMap;
 use std::io::Read;
+use std::sync::RwLock;
 use config::SalesforceConfig;
-use reqwest::{Client as ReqClient, Request, RequestBuilder, Response, Method};
+use salesforce::auth::{self, AuthStrategy};
+use salesforce::limits::{self, ApiUsage};
+use salesforce::errors::SalesforceError;
+use rand::{self, Rng};
+use std::thread::sleep;
+use std::time::Duration as StdDuration;
+use chrono::prelude::*;
+use time::Duration;
+use reqwest::{Client as ReqClient, Request, RequestBuilder, Response, Method, StatusCode};
 use reqwest::header::{Headers, Authorization, Bearer, ContentType};
-use serde_json::value::Value;
+use logging::Event;
+use metrics;
+
+//renew the session this many seconds before it is expected to lapse
+const SESSION_RENEW_MARGIN: i64 = 60;
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginData {
@@ -11,122 +24,292 @@ pub struct LoginData {
     pub instance_url: String,
     id: String,
     token_type: String,
+    #[serde(default)]
     issued_at: String,
+    #[serde(default)]
     signature: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(skip)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl LoginData {
+    fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Utc::now() + Duration::seconds(SESSION_RENEW_MARGIN) >= expires_at,
+            None => false,
+        }
+    }
+
+    fn set_expiry(&mut self, session_timeout: u64) {
+        //issued_at is given in milliseconds since epoch
+        let issued = self.issued_at
+            .parse::<i64>()
+            .map(|millis| Utc.timestamp(millis / 1000, 0))
+            .unwrap_or(Utc::now());
+        self.expires_at = Some(issued + Duration::seconds(session_timeout as i64));
+    }
 }
 
 pub struct Client {
-    login_data: Option<LoginData>,
+    login_data: RwLock<Option<LoginData>>,
+    config: Option<&'static SalesforceConfig>,
+    auth: Option<Box<AuthStrategy>>,
     client: ReqClient,
+    api_usage: RwLock<Option<ApiUsage>>,
 }
 
 impl Client {
     pub fn new(login_data: Option<LoginData>) -> Client {
         Client {
-            login_data: login_data,
+            login_data: RwLock::new(login_data),
+            config: None,
+            auth: None,
             client: ReqClient::new(),
+            api_usage: RwLock::new(None),
         }
     }
 
     pub fn get_login_data(self) -> LoginData {
-        self.login_data.unwrap()
+        self.login_data.into_inner().unwrap().unwrap()
     }
 
     pub fn is_connected(&self) -> bool {
-        let option = self.login_data.as_ref();
-        match option {
-            None => false,
-            Some(_value) => true,
-        }
+        self.login_data.read().unwrap().is_some()
     }
 
-    pub fn connect(mut self, config: &'static SalesforceConfig) -> Client {
+    pub fn connect(mut self, config: &'static SalesforceConfig) -> Result<Client, SalesforceError> {
+        self.config = Some(config);
+        self.auth = Some(auth::from_config(config));
         if self.is_connected() {
-            return self;
+            return Ok(self);
         }
+        let ld = self.login(config)?;
+        *self.login_data.write().unwrap() = Some(ld);
+        Ok(self)
+    }
 
-        let password = format!("{}{}", config.password, config.sec_token);
-        let mut params = HashMap::new();
-        params.insert("grant_type", "password");
-        params.insert("client_id", config.client_id.as_str());
-        params.insert("client_secret", config.client_secret.as_str());
-        params.insert("username", config.username.as_str());
-        params.insert("password", password.as_str());
-        let mut req = self.client.post(config.uri.as_str());
-        let req = req.form(&params).build().unwrap();
-        let mut response = self.call(req).unwrap();
-	//println!("{:?}",response);
-	//let mut res = String::new();
-	//response.read_to_string(&mut res);
-	//println!("{}", res);
-        let ld: LoginData = response.json().map_err(|err| println!("Login failed: {}", err)).unwrap();
-        self.login_data = Some(ld);
-        self
-    }
-
-    pub fn print_login_data(&self) {
-        let ld = self.login_data.as_ref().unwrap();
-        println!("Access Token: {}", ld.access_token);
-        println!("Instance Url: {}", ld.instance_url);
-    }
-
-    pub fn get_resource<F>(&self, req_builder: F) -> Result<String, String>
+    pub fn get_resource<F>(&self, req_builder: F) -> Result<String, SalesforceError>
+        where F: Fn(&String) -> String
+    {
+        let mut response = self.send_auth_request(Method::Get, None, req_builder)?;
+        let mut result = String::new();
+        let _bytes_read = response.read_to_string(&mut result);
+        Ok(result)
+    }
+
+    //returns the body along with the value of the given response header
+    pub fn get_resource_with_header<F>(&self, header: &str, req_builder: F)
+        -> Result<(String, Option<String>), SalesforceError>
         where F: Fn(&String) -> String
     {
-        let mut req = self.build_auth_request(Method::Get, req_builder);
-        let mut response = try!(self.call(req.build().unwrap()));
+        let mut response = self.send_auth_request(Method::Get, None, req_builder)?;
+        let header_value = response.headers()
+            .get_raw(header)
+            .and_then(|raw| raw.one())
+            .map(|value| String::from_utf8_lossy(value).into_owned());
+        let mut result = String::new();
+        let _bytes_read = response.read_to_string(&mut result);
+        Ok((result, header_value))
+    }
+
+    pub fn update_resource<F>(&self, data: String, req_builder: F) -> Result<String, SalesforceError>
+        where F: Fn(&String) -> String
+    {
+        let mut response = self.send_auth_request(Method::Patch, Some(&data), req_builder)?;
         let mut result = String::new();
         let _bytes_read = response.read_to_string(&mut result);
         Ok(result)
     }
 
-    pub fn update_resource<F>(&self, data: String, req_builder: F) -> Result<String,String>
+    pub fn create_resource<F>(&self,  data: String, req_builder: F) -> Result<String, SalesforceError>
         where F: Fn(&String) -> String
     {
-        let mut builder = self.build_auth_request(Method::Patch, req_builder);
-        builder.body(data);
-        let mut req = builder.build().unwrap();
-        req.headers_mut().set(ContentType::json());
-        let mut response = try!(self.call(req));
+        let mut response = self.send_auth_request(Method::Post, Some(&data), req_builder)?;
         let mut result = String::new();
         let _bytes_read = response.read_to_string(&mut result);
         Ok(result)
     }
 
-    pub fn create_resource<F>(&self,  data: String, req_builder: F) -> Result<String,String>
+    pub fn delete_resource<F>(&self, req_builder: F) -> Result<String, SalesforceError>
         where F: Fn(&String) -> String
     {
-        let mut builder = self.build_auth_request(Method::Post, req_builder);
-        builder.body(data);
-        let mut req = builder.build().unwrap();
-        req.headers_mut().set(ContentType::json());
-        let mut response = try!(self.call(req));
+        let mut response = self.send_auth_request(Method::Delete, None, req_builder)?;
         let mut result = String::new();
         let _bytes_read = response.read_to_string(&mut result);
         Ok(result)
     }
 
-    fn call(&self, req: Request) -> Result<Response,String> {
-        let mut response = try!(self.client
+    fn login(&self, config: &SalesforceConfig) -> Result<LoginData, SalesforceError> {
+        let auth = self.auth.as_ref()
+            .ok_or(SalesforceError::Auth("No authentication flow configured".to_owned()))?;
+        Event::info(&format!("Logging in using {} flow", auth.name())).emit();
+        let params = auth.token_params().map_err(SalesforceError::Auth)?;
+        let params: HashMap<&str, &str> = params.iter()
+            .map(|&(ref key, ref value)| (key.as_str(), value.as_str()))
+            .collect();
+        self.request_token(config, &params)
+    }
+
+    fn refresh(&self, config: &SalesforceConfig, refresh_token: &str) -> Result<LoginData, SalesforceError> {
+        let mut params = HashMap::new();
+        params.insert("grant_type", "refresh_token");
+        params.insert("client_id", config.client_id.as_str());
+        params.insert("client_secret", config.client_secret.as_str());
+        params.insert("refresh_token", refresh_token);
+        let mut ld = self.request_token(config, &params)?;
+        //the refresh token is not returned again, keep the one we have
+        ld.refresh_token = Some(refresh_token.to_owned());
+        Ok(ld)
+    }
+
+    fn request_token(&self, config: &SalesforceConfig, params: &HashMap<&str, &str>)
+        -> Result<LoginData, SalesforceError>
+    {
+        //a token request creates nothing, so it is repeated like a read
+        let mut response = self.with_retry(true, || {
+            let mut req = self.client.post(config.uri.as_str());
+            let req = req.form(params).build().map_err(|err| SalesforceError::Transport(err.to_string()))?;
+            self.call(req)
+        }).map_err(|err| match err {
+            //the token endpoint answers bad credentials with 400
+            SalesforceError::Validation { message, .. } => SalesforceError::Auth(message),
+            err => err,
+        })?;
+        let mut ld: LoginData = response.json().map_err(|err| SalesforceError::Auth(err.to_string()))?;
+        ld.set_expiry(config.session_timeout);
+        Ok(ld)
+    }
+
+    fn renew_session(&self) -> Result<(), SalesforceError> {
+        let config = self.config.ok_or(SalesforceError::Auth("Client not connected".to_owned()))?;
+        let mut guard = self.login_data.write().unwrap();
+        let refresh_token = guard.as_ref().and_then(|ld| ld.refresh_token.clone());
+        let ld = match refresh_token {
+            Some(token) => self.refresh(config, &token)
+                .or_else(|err| {
+                    Event::warn("Token refresh failed, logging in again").with_error(&err.to_string()).emit();
+                    self.login(config)
+                })?,
+            None => self.login(config)?,
+        };
+        Event::info("Salesforce session renewed").emit();
+        *guard = Some(ld);
+        Ok(())
+    }
+
+    fn is_session_expired(&self) -> bool {
+        match self.login_data.read().unwrap().as_ref() {
+            Some(ld) => ld.is_expired(),
+            None => true,
+        }
+    }
+
+    fn send_auth_request<F>(&self, method: Method, data: Option<&String>, req_builder: F)
+        -> Result<Response, SalesforceError>
+        where F: Fn(&String) -> String
+    {
+        //creates and deletes are only sent again when they cannot have been applied
+        let idempotent = method == Method::Get || method == Method::Patch;
+        self.with_retry(idempotent, || self.send_auth_request_once(method.clone(), data, &req_builder))
+    }
+
+    fn send_auth_request_once<F>(&self, method: Method, data: Option<&String>, req_builder: &F)
+        -> Result<Response, SalesforceError>
+        where F: Fn(&String) -> String
+    {
+        if self.config.is_some() && self.is_session_expired() {
+            self.renew_session()?;
+        }
+        let req = self.build_auth_request(method.clone(), data, req_builder)?;
+        let response = self.execute(req)?;
+        //session expired or was revoked: log in again and retry once
+        if response.status() == StatusCode::Unauthorized && self.config.is_some() {
+            Event::warn("Salesforce session invalid, renewing").emit();
+            self.renew_session()?;
+            let req = self.build_auth_request(method, data, req_builder)?;
+            return self.call(req);
+        }
+        Client::check_response(response)
+    }
+
+    //transient failures are retried with exponential backoff and full jitter
+    fn with_retry<T, F>(&self, idempotent: bool, mut request: F) -> Result<T, SalesforceError>
+        where F: FnMut() -> Result<T, SalesforceError>
+    {
+        let retry = self.config.map(|config| config.http_retry.clone()).unwrap_or_default();
+        let mut attempt = 1;
+        loop {
+            match request() {
+                Err(ref err) if err.is_retryable(idempotent) && attempt < retry.max_attempts => {
+                    let delay = rand::thread_rng().gen_range(0, retry.max_delay(attempt) + 1);
+                    Event::warn(&format!("Request failed, retrying in {} ms", delay))
+                        .with_count(attempt as u64)
+                        .with_error(&err.to_string())
+                        .emit();
+                    sleep(StdDuration::from_millis(delay));
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn call(&self, req: Request) -> Result<Response, SalesforceError> {
+        let response = self.execute(req)?;
+        Client::check_response(response)
+    }
+
+    //every request goes through here to count it and read the usage salesforce reports back
+    fn execute(&self, req: Request) -> Result<Response, SalesforceError> {
+        metrics::inc_counter(metrics::API_CALLS, &[], 1);
+        let response = self.client
             .execute(req)
-            .map_err(|err| err.to_string()));
+            .map_err(|err| SalesforceError::from_transport(&err))?;
+        if let Some(usage) = limits::usage_from_headers(response.headers()) {
+            self.set_api_usage(usage);
+        }
+        Ok(response)
+    }
+
+    pub fn get_api_usage(&self) -> Option<ApiUsage> {
+        *self.api_usage.read().unwrap()
+    }
+
+    pub fn set_api_usage(&self, usage: ApiUsage) {
+        metrics::set_gauge(metrics::API_USAGE, &[], usage.used as f64);
+        metrics::set_gauge(metrics::API_LIMIT, &[], usage.max as f64);
+        *self.api_usage.write().unwrap() = Some(usage);
+    }
+
+    fn check_response(mut response: Response) -> Result<Response, SalesforceError> {
         if !response.status().is_success() {
             let mut result = String::new();
             let _= response.read_to_string(&mut result);
-            return Err(format!("{} {}", response.status(), result));
+            return Err(SalesforceError::from_response(response.status().as_u16(), &result));
         }
         Ok(response)
     }
 
-    fn build_auth_request<F>(&self,method: Method, req_builder: F) -> RequestBuilder
+    fn build_auth_request<F>(&self, method: Method, data: Option<&String>, req_builder: &F)
+        -> Result<Request, SalesforceError>
         where F: Fn(&String) -> String
     {
-        let ld = self.login_data.as_ref().unwrap();
+        let guard = self.login_data.read().unwrap();
+        let ld = guard.as_ref().ok_or(SalesforceError::Auth("Client not connected".to_owned()))?;
         let uri = req_builder(&ld.instance_url);
-        let mut req = self.client.request(method, uri.as_str());
+        let mut builder: RequestBuilder = self.client.request(method, uri.as_str());
         let mut headers = Headers::new();
         headers.set(Authorization(Bearer { token: ld.access_token.clone() }));
-        req.headers(headers);
-        req
+        builder.headers(headers);
+        if let Some(body) = data {
+            builder.body(body.clone());
+        }
+        let mut req = builder.build().map_err(|err| SalesforceError::Transport(err.to_string()))?;
+        if data.is_some() {
+            req.headers_mut().set(ContentType::json());
+        }
+        Ok(req)
     }
 }
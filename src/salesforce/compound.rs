@@ -0,0 +1,75 @@
+use serde_json::{Map, Value};
+use config::CompoundMode;
+use salesforce::objects::{Field, FieldValue};
+
+pub fn is_compound(field: &Field) -> bool {
+    field.sf_type == "address" || field.sf_type == "location"
+}
+
+//fields stored as table columns, either the components or the compound itself
+pub fn column_fields(fields: &[Field], mode: CompoundMode) -> Vec<&Field> {
+    fields.iter()
+        .filter(|field| match mode {
+            CompoundMode::Split => !is_compound(field),
+            CompoundMode::Json => field.compound_field_name.is_none(),
+        })
+        .collect()
+}
+
+pub fn components<'a>(compound: &Field, fields: &'a [Field]) -> Vec<&'a Field> {
+    fields.iter()
+        .filter(|field| field.compound_field_name.as_ref() == Some(&compound.name))
+        .collect()
+}
+
+//BillingAddress -> Billing, Location__c -> Location__
+fn component_prefix(compound: &Field) -> &str {
+    let name = compound.name.as_str();
+    if name.ends_with("Address") {
+        &name[..name.len() - "Address".len()]
+    } else if name.ends_with("__c") {
+        &name[..name.len() - 1]
+    } else {
+        name
+    }
+}
+
+//key of a component in the compound value, BillingPostalCode -> postalCode, Location__Latitude__s -> latitude
+pub fn component_key(compound: &Field, component: &Field) -> Option<String> {
+    let prefix = component_prefix(compound);
+    if !component.name.starts_with(prefix) {
+        return None;
+    }
+    let name = &component.name[prefix.len()..];
+    let name = if name.ends_with("__s") { &name[..name.len() - 3] } else { name };
+    let mut chars = name.chars();
+    chars.next().map(|first| first.to_lowercase().chain(chars).collect())
+}
+
+//compound value of a record, bulk results only contain the components
+pub fn assemble(compound: &Field, fields: &[Field], row: &Value) -> Value {
+    if !row[&compound.name].is_null() {
+        return row[&compound.name].clone();
+    }
+    let mut value = Map::new();
+    for component in components(compound, fields) {
+        if let Some(key) = component_key(compound, component) {
+            value.insert(key, FieldValue::from_json(component, &row[&component.name]).to_json());
+        }
+    }
+    if value.values().all(|val| val.is_null()) {
+        return Value::Null;
+    }
+    Value::Object(value)
+}
+
+//writable components of a compound value as (field name, value) pairs
+pub fn expand(compound: &Field, fields: &[Field], value: &Value) -> Vec<(String, Value)> {
+    components(compound, fields)
+        .into_iter()
+        .filter(|component| component.updateable)
+        .filter_map(|component| {
+            component_key(compound, component).map(|key| (component.name.clone(), value[&key].clone()))
+        })
+        .collect()
+}
@@ -0,0 +1,222 @@
+use serde_json::{self, Value};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use reqwest;
+
+//the org ran out of api requests, sent with status 403
+const REQUEST_LIMIT_EXCEEDED: &str = "REQUEST_LIMIT_EXCEEDED";
+//the record was deleted in salesforce already
+pub const ENTITY_IS_DELETED: &str = "ENTITY_IS_DELETED";
+//rejections caused by concurrent work in the org, the same data is accepted later
+const TRANSIENT_CODES: [&str; 2] = ["UNABLE_TO_LOCK_ROW", "REQUEST_RUNNING_TOO_LONG"];
+
+#[derive(Clone, Debug)]
+pub enum SalesforceError {
+    //the connection could not be opened, the request never left the client
+    Connect(String),
+    //connection failures and timeouts, the request may have reached salesforce
+    Transport(String),
+    //login failed or the session was rejected
+    Auth(String),
+    RateLimited(String),
+    NotFound(String),
+    //the request was rejected because of its content
+    Validation {
+        status: u16,
+        error_code: String,
+        message: String,
+        fields: Vec<String>,
+    },
+    Server {
+        status: u16,
+        message: String,
+    },
+    //the response did not have the expected shape
+    InvalidResponse(String),
+}
+
+impl SalesforceError {
+    //rest errors come as [{"message": .., "errorCode": .., "fields": [..]}], oauth errors as {"error": ..}
+    pub fn from_response(status: u16, body: &str) -> SalesforceError {
+        let json: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+        let error = if json.is_array() { &json[0] } else { &json };
+        let message = error["message"].as_str()
+            .or(error["error_description"].as_str())
+            .map(|message| message.to_owned())
+            .unwrap_or(body.to_owned());
+        SalesforceError::from_error(status, error, message)
+    }
+
+    //collection results carry one error list per record, [{"statusCode": .., "message": .., "fields": [..]}]
+    pub fn from_record_errors(errors: &Value) -> SalesforceError {
+        let error = &errors[0];
+        let message = error["message"].as_str()
+            .map(|message| message.to_owned())
+            .unwrap_or(errors.to_string());
+        let error = json!({
+            "errorCode": error["statusCode"],
+            "fields": error["fields"],
+        });
+        SalesforceError::from_error(400, &error, message)
+    }
+
+    fn from_error(status: u16, error: &Value, message: String) -> SalesforceError {
+        let error_code = error["errorCode"].as_str()
+            .or(error["error"].as_str())
+            .unwrap_or("")
+            .to_owned();
+        match status {
+            429 => SalesforceError::RateLimited(message),
+            403 if error_code == REQUEST_LIMIT_EXCEEDED => SalesforceError::RateLimited(message),
+            401 => SalesforceError::Auth(message),
+            404 => SalesforceError::NotFound(message),
+            status if status >= 500 => SalesforceError::Server {
+                status: status,
+                message: message,
+            },
+            _ => SalesforceError::Validation {
+                status: status,
+                error_code: error_code,
+                message: message,
+                fields: error["fields"].as_array()
+                    .map(|fields| fields.iter().filter_map(|field| field.as_str().map(|field| field.to_owned())).collect())
+                    .unwrap_or(vec!()),
+            },
+        }
+    }
+
+    //a refused connection is the only transport failure known to happen before the request was sent
+    pub fn from_transport(err: &reqwest::Error) -> SalesforceError {
+        let kind = err.get_ref()
+            .and_then(|err| err.downcast_ref::<io::Error>())
+            .map(|io_err| io_err.kind());
+        match kind {
+            Some(io::ErrorKind::ConnectionRefused) | Some(io::ErrorKind::AddrNotAvailable) => {
+                SalesforceError::Connect(err.to_string())
+            },
+            _ => SalesforceError::Transport(err.to_string()),
+        }
+    }
+
+    //failures worth retrying as they are, the same request may succeed later
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            SalesforceError::Connect(_) | SalesforceError::Transport(_) | SalesforceError::RateLimited(_) |
+            SalesforceError::Server { .. } => true,
+            SalesforceError::Validation { ref error_code, .. } => TRANSIENT_CODES.contains(&error_code.as_str()),
+            _ => false,
+        }
+    }
+
+    pub fn error_code(&self) -> Option<&str> {
+        match *self {
+            SalesforceError::Validation { ref error_code, .. } => Some(error_code.as_str()),
+            _ => None,
+        }
+    }
+
+    //salesforce did not process the request, so even a create can be sent again without duplicating records
+    pub fn is_unprocessed(&self) -> bool {
+        match *self {
+            SalesforceError::Connect(_) | SalesforceError::RateLimited(_) => true,
+            _ => false,
+        }
+    }
+
+    //requests that are not idempotent, like creates and deletes, are only sent again when they were not processed
+    pub fn is_retryable(&self, idempotent: bool) -> bool {
+        self.is_unprocessed() || (idempotent && self.is_transient())
+    }
+}
+
+impl Display for SalesforceError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            SalesforceError::Connect(ref message) => write!(f, "Connection failed: {}", message),
+            SalesforceError::Transport(ref message) => write!(f, "Transport error: {}", message),
+            SalesforceError::Auth(ref message) => write!(f, "Authentication failed: {}", message),
+            SalesforceError::RateLimited(ref message) => write!(f, "{}: {}", REQUEST_LIMIT_EXCEEDED, message),
+            SalesforceError::NotFound(ref message) => write!(f, "Not found: {}", message),
+            SalesforceError::Validation { status, ref error_code, ref message, ref fields } => {
+                write!(f, "{} {}: {}", status, error_code, message)?;
+                if !fields.is_empty() {
+                    write!(f, " ({})", fields.join(", "))?;
+                }
+                Ok(())
+            },
+            SalesforceError::Server { status, ref message } => write!(f, "Server error {}: {}", status, message),
+            SalesforceError::InvalidResponse(ref message) => write!(f, "Invalid response: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_response_errors() {
+        match SalesforceError::from_response(403, r#"[{"message": "Limit", "errorCode": "REQUEST_LIMIT_EXCEEDED"}]"#) {
+            SalesforceError::RateLimited(message) => assert_eq!(message, "Limit"),
+            err => panic!("unexpected {:?}", err),
+        }
+        match SalesforceError::from_response(400, r#"[{"message": "Bad", "errorCode": "REQUIRED_FIELD_MISSING", "fields": ["Name"]}]"#) {
+            SalesforceError::Validation { status, error_code, fields, .. } => {
+                assert_eq!(status, 400);
+                assert_eq!(error_code, "REQUIRED_FIELD_MISSING");
+                assert_eq!(fields, vec!("Name".to_owned()));
+            },
+            err => panic!("unexpected {:?}", err),
+        }
+        match SalesforceError::from_response(503, "unavailable") {
+            SalesforceError::Server { status, message } => {
+                assert_eq!(status, 503);
+                assert_eq!(message, "unavailable");
+            },
+            err => panic!("unexpected {:?}", err),
+        }
+    }
+
+    #[test]
+    fn retries_transient_errors_of_idempotent_requests() {
+        let server = SalesforceError::from_response(500, "error");
+        let timeout = SalesforceError::Transport("timed out".to_owned());
+        let locked = SalesforceError::from_response(400, r#"[{"message": "Locked", "errorCode": "UNABLE_TO_LOCK_ROW"}]"#);
+        for err in &[server, timeout, locked] {
+            assert!(err.is_transient());
+            assert!(!err.is_unprocessed());
+            assert!(err.is_retryable(true));
+            //a create may already have been committed
+            assert!(!err.is_retryable(false));
+        }
+    }
+
+    #[test]
+    fn retries_unprocessed_requests_even_if_not_idempotent() {
+        let refused = SalesforceError::Connect("connection refused".to_owned());
+        let limited = SalesforceError::from_response(429, "too many requests");
+        for err in &[refused, limited] {
+            assert!(err.is_unprocessed());
+            assert!(err.is_retryable(true));
+            assert!(err.is_retryable(false));
+        }
+    }
+
+    #[test]
+    fn never_retries_permanent_errors() {
+        let invalid = SalesforceError::from_response(400, r#"[{"message": "Bad", "errorCode": "INVALID_FIELD"}]"#);
+        let auth = SalesforceError::from_response(401, r#"{"error": "invalid_grant", "error_description": "expired"}"#);
+        for err in &[invalid, auth] {
+            assert!(!err.is_retryable(true));
+            assert!(!err.is_retryable(false));
+        }
+    }
+
+    #[test]
+    fn reads_record_errors() {
+        let errors = json!([{"statusCode": "ENTITY_IS_DELETED", "message": "deleted", "fields": []}]);
+        let err = SalesforceError::from_record_errors(&errors);
+        assert_eq!(err.error_code(), Some(ENTITY_IS_DELETED));
+        assert!(!err.is_retryable(true));
+    }
+}
@@ -0,0 +1,127 @@
+use config::ApiBudget;
+use reqwest::header::Headers;
+
+//sent with every rest response, e.g. api-usage=25/15000
+pub const LIMIT_INFO_HEADER: &str = "Sforce-Limit-Info";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ApiUsage {
+    pub used: u64,
+    pub max: u64,
+}
+
+impl ApiUsage {
+    pub fn share(&self) -> f64 {
+        if self.max == 0 {
+            return 0.0;
+        }
+        self.used as f64 / self.max as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Throttle {
+    Normal,
+    //usage is close to the budget, cycles are spaced further apart
+    SlowDown,
+    //the budget is used up, only the usage is checked until it drops again
+    Pause,
+}
+
+impl Throttle {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Throttle::Normal => "normal",
+            Throttle::SlowDown => "slow_down",
+            Throttle::Pause => "pause",
+        }
+    }
+}
+
+//none when the header is missing or cannot be read, the last known usage is kept
+pub fn usage_from_headers(headers: &Headers) -> Option<ApiUsage> {
+    headers.get_raw(LIMIT_INFO_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|value| String::from_utf8(value.to_vec()).ok())
+        .and_then(|value| parse_limit_info(&value))
+}
+
+//other entries like per-app-api-usage are ignored
+pub fn parse_limit_info(value: &str) -> Option<ApiUsage> {
+    let usage = value.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("api-usage"), Some(usage)) => Some(usage),
+                _ => None,
+            }
+        })
+        .next()?;
+    let mut parts = usage.splitn(2, '/');
+    let used = parts.next()?.trim().parse::<u64>().ok()?;
+    let max = parts.next()?.trim().parse::<u64>().ok()?;
+    Some(ApiUsage {
+        used: used,
+        max: max,
+    })
+}
+
+pub fn get_throttle(usage: &ApiUsage, budget: &ApiBudget) -> Throttle {
+    let budget_share = budget.max_share;
+    let share = usage.share();
+    if share >= budget_share {
+        Throttle::Pause
+    } else if share >= budget_share * budget.slow_down_at {
+        Throttle::SlowDown
+    } else {
+        Throttle::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget() -> ApiBudget {
+        ApiBudget {
+            max_share: 0.5,
+            slow_down_at: 0.8,
+            slow_down_factor: 4,
+        }
+    }
+
+    #[test]
+    fn parses_api_usage_among_other_entries() {
+        let usage = parse_limit_info("per-app-api-usage=2/250(appName=sync), api-usage=25/15000");
+        assert_eq!(usage, Some(ApiUsage { used: 25, max: 15000 }));
+    }
+
+    #[test]
+    fn ignores_malformed_limit_info() {
+        assert_eq!(parse_limit_info(""), None);
+        assert_eq!(parse_limit_info("api-usage"), None);
+        assert_eq!(parse_limit_info("api-usage=25"), None);
+        assert_eq!(parse_limit_info("api-usage=25/"), None);
+        assert_eq!(parse_limit_info("api-usage=abc/15000"), None);
+        assert_eq!(parse_limit_info("per-app-api-usage=2/250"), None);
+    }
+
+    #[test]
+    fn reads_usage_from_headers() {
+        let mut headers = Headers::new();
+        assert_eq!(usage_from_headers(&headers), None);
+        headers.set_raw(LIMIT_INFO_HEADER, "api-usage=not a number");
+        assert_eq!(usage_from_headers(&headers), None);
+        headers.set_raw(LIMIT_INFO_HEADER, "api-usage=100/1000");
+        assert_eq!(usage_from_headers(&headers), Some(ApiUsage { used: 100, max: 1000 }));
+    }
+
+    #[test]
+    fn throttles_by_share_of_the_budget() {
+        let budget = budget();
+        assert_eq!(get_throttle(&ApiUsage { used: 300, max: 1000 }, &budget), Throttle::Normal);
+        assert_eq!(get_throttle(&ApiUsage { used: 400, max: 1000 }, &budget), Throttle::SlowDown);
+        assert_eq!(get_throttle(&ApiUsage { used: 500, max: 1000 }, &budget), Throttle::Pause);
+        assert_eq!(get_throttle(&ApiUsage { used: 0, max: 0 }, &budget), Throttle::Normal);
+    }
+}
@@ -1,37 +1,120 @@
 pub mod objects;
 pub mod client;
+pub mod auth;
+pub mod bulk;
+pub mod compound;
+pub mod errors;
+pub mod limits;
 
 use std::str;
 use std::ops::Sub;
 use serde_json::{self, Value};
+use serde::de::DeserializeOwned;
 use config::SalesforceConfig;
 use self::objects::{SObject, SObjectList, SObjectDescribe, SObjectConfiguration,
                     SObjectRowResultWrapper};
 use chrono::prelude::*;
 use time::Duration;
 use std::collections::HashMap;
+use std::cmp::max;
+use std::thread::sleep;
+use std::time::Duration as StdDuration;
 use salesforce::client::Client;
+use salesforce::bulk::BulkQueryJob;
 use db::objects::ObjectConfig;
 use db::record::Record;
+use logging::Event;
+use salesforce::limits::{ApiUsage, Throttle};
+use salesforce::errors::SalesforceError;
+use std::sync::Mutex;
+
+const BULK_POLL_INTERVAL_MS: u64 = 2000;
+const MODSTAMP_FIELD: &str = "SystemModstamp";
+const DELETED_RETENTION_DAYS: i64 = 29;
+//maximum number of records accepted by the sObject Collections endpoints
+const COLLECTION_BATCH_SIZE: usize = 200;
+//ids per query when reading current versions, keeps the url short
+const VERSION_BATCH_SIZE: usize = 100;
 
 pub struct Salesforce {
     config: &'static SalesforceConfig,
     client: Client,
+    //last throttle state, changes are reported once
+    throttle: Mutex<Throttle>,
 }
 
 impl Salesforce {
-    pub fn new(config: &'static SalesforceConfig) -> Salesforce {
-        let client: Client = Client::new(None).connect(config);
-        client.print_login_data();
-        Salesforce {
+    pub fn new(config: &'static SalesforceConfig) -> Result<Salesforce, SalesforceError> {
+        let client: Client = Client::new(None).connect(config)?;
+        Ok(Salesforce {
             config: config,
             client: client,
+            throttle: Mutex::new(Throttle::Normal),
+        })
+    }
+
+    //org limits from the limits resource, also refreshes the tracked api usage
+    pub fn get_limits(&self) -> Result<Value, SalesforceError> {
+        let req_builder = |uri: &String| format!("{}/services/data/{}/limits", uri, self.config.api_version);
+        let posted_str = self.client.get_resource(req_builder)?;
+        let limits: Value = parse_json(&posted_str)?;
+        let daily = &limits["DailyApiRequests"];
+        if let (Some(max), Some(remaining)) = (daily["Max"].as_u64(), daily["Remaining"].as_u64()) {
+            self.client.set_api_usage(ApiUsage {
+                used: max.saturating_sub(remaining),
+                max: max,
+            });
         }
+        Ok(limits)
     }
-    pub fn get_objects(&self) -> Result<Vec<SObject>, String> {
+
+    pub fn get_api_usage(&self) -> Option<ApiUsage> {
+        self.client.get_api_usage()
+    }
+
+    //compares the tracked usage with the configured budget and warns when the state changes
+    pub fn get_throttle(&self) -> Throttle {
+        let throttle = match (self.config.api_budget.as_ref(), self.get_api_usage()) {
+            (Some(budget), Some(usage)) => limits::get_throttle(&usage, budget),
+            _ => Throttle::Normal,
+        };
+        let mut last = self.throttle.lock().unwrap();
+        if *last != throttle {
+            let message = match throttle {
+                Throttle::Normal => "Api usage back within budget",
+                Throttle::SlowDown => "Api usage close to budget, slowing down",
+                Throttle::Pause => "Api budget used up, pausing sync",
+            };
+            let usage = self.get_api_usage().map(|usage| format!("{}/{}", usage.used, usage.max)).unwrap_or_default();
+            Event::warn(&format!("{} ({})", message, usage)).emit();
+            *last = throttle;
+        }
+        throttle
+    }
+
+    //while paused nothing else calls the api, so the usage is refreshed from the limits resource
+    pub fn check_budget(&self) -> Throttle {
+        let throttle = self.get_throttle();
+        if throttle != Throttle::Pause {
+            return throttle;
+        }
+        if let Err(err) = self.get_limits() {
+            Event::error("Error reading limits").with_error(&err.to_string()).emit();
+        }
+        self.get_throttle()
+    }
+
+    pub fn get_throttled_timeout(&self, timeout: u64) -> u64 {
+        match (self.get_throttle(), self.config.api_budget.as_ref()) {
+            (Throttle::Normal, _) | (_, None) => timeout,
+            (_, Some(budget)) => timeout.saturating_mul(budget.slow_down_factor),
+        }
+    }
+
+    pub fn get_objects(&self) -> Result<Vec<SObject>, SalesforceError> {
         let req_builder = |uri: &String| format!("{}/services/data/{}/sobjects", uri, self.config.api_version);
-        let posted_str = self.client.get_resource(req_builder).unwrap();
-        let list: SObjectList = serde_json::from_str(posted_str.as_str()).unwrap();
+        let posted_str = self.client.get_resource(req_builder)?;
+        let list: SObjectList = parse_json(&posted_str)?;
         let filtered_list: Vec<SObject> = list.sobjects
             .into_iter()
             .filter(|x| (x.createable && x.queryable && x.layoutable) || x.custom_setting)
@@ -39,125 +122,389 @@ impl Salesforce {
         Ok(filtered_list)
     }
 
-    pub fn describe_object(&self, object_name: &str) -> Result<SObjectDescribe, String> {
+    pub fn describe_object(&self, object_name: &str) -> Result<SObjectDescribe, SalesforceError> {
         let req_builder = |uri: &String| {
             format!("{}/services/data/{}/sobjects/{}/describe",
                     uri,
                     self.config.api_version,
                     object_name)
         };
-        let posted_str = self.client.get_resource(req_builder).unwrap();
-        let object: SObjectDescribe = serde_json::from_str(posted_str.as_str()).unwrap();
+        let posted_str = self.client.get_resource(req_builder)?;
+        let object: SObjectDescribe = parse_json(&posted_str)?;
         Ok(object)
     }
 
     pub fn get_last_updated_records(&self,
                                     object_config: &ObjectConfig,
-                                    time_sec: i64)
-                                    -> Result<SObjectRowResultWrapper, String> {
-        let date_diff: DateTime<Utc> = Utc::now().sub(Duration::minutes(time_sec));
-        let query = format!("SELECT+{}+FROM+{}+WHERE+lastmodifieddate>{}",
-                            object_config.get_field_names().join(","),
+                                    since: &DateTime<Utc>)
+                                    -> Result<SObjectRowResultWrapper, SalesforceError> {
+        let mut fields = object_config.get_field_names();
+        if !fields.iter().any(|name| name == MODSTAMP_FIELD) {
+            fields.push(MODSTAMP_FIELD.to_owned());
+        }
+        let query = format!("SELECT+{}+FROM+{}+WHERE+{}>{}{}+ORDER+BY+{}",
+                            fields.join(","),
                             object_config.name,
-                            date_diff.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+                            MODSTAMP_FIELD,
+                            since.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                            and_filter(object_config.get_filter()),
+                            MODSTAMP_FIELD);
         //println!("{}",query);
         let req_builder = |uri: &String| format!("{}/services/data/{}/query/?q={}", 
                                                     uri, self.config.api_version, query);
-        let posted_str = self.client.get_resource(req_builder).unwrap();
+        let posted_str = self.client.get_resource(req_builder)?;
         //println!("{}",posted_str);
-        let v: Value = serde_json::from_str(posted_str.as_str()).unwrap();
+        let v: Value = parse_json(&posted_str)?;
         if !v["records"].is_array() {
-            return Err("Error fetching data".to_owned());
+            return Err(SalesforceError::InvalidResponse("Error fetching data".to_owned()));
+        }
+        Ok(SObjectRowResultWrapper::new(object_config, v))
+    }
+
+    //current salesforce version of the given records, keyed by id
+    pub fn get_current_versions(&self, object_config: &ObjectConfig, sfids: &[String])
+        -> Result<HashMap<String, Value>, SalesforceError> {
+        let mut fields = object_config.get_field_names();
+        if !fields.iter().any(|name| name == MODSTAMP_FIELD) {
+            fields.push(MODSTAMP_FIELD.to_owned());
+        }
+        let mut versions = HashMap::new();
+        for batch in sfids.chunks(VERSION_BATCH_SIZE) {
+            let ids: Vec<String> = batch.iter().map(|sfid| format!("%27{}%27", sfid)).collect();
+            let query = format!("SELECT+{}+FROM+{}+WHERE+Id+IN+({})",
+                                fields.join(","),
+                                object_config.name,
+                                ids.join(","));
+            let req_builder = |uri: &String| format!("{}/services/data/{}/query/?q={}",
+                                                        uri, self.config.api_version, query);
+            let posted_str = self.client.get_resource(req_builder)?;
+            let v: Value = parse_json(&posted_str)?;
+            let records = v["records"].as_array().ok_or(SalesforceError::InvalidResponse("Error fetching current versions".to_owned()))?;
+            for record in records {
+                if let Some(id) = record["Id"].as_str() {
+                    versions.insert(id.to_owned(), record.clone());
+                }
+            }
         }
-        Ok(SObjectRowResultWrapper::new(&object_config.name, &object_config.fields, v))
+        Ok(versions)
+    }
+
+    //ids of records changed since the given time that no longer match the object filter
+    pub fn get_unmatched_records(&self,
+                                 object_config: &ObjectConfig,
+                                 since: &DateTime<Utc>)
+                                 -> Result<Vec<String>, SalesforceError> {
+        let filter = match object_config.get_filter() {
+            Some(filter) => filter,
+            None => return Ok(vec!()),
+        };
+        let query = format!("SELECT+Id+FROM+{}+WHERE+{}>{}+AND+NOT+({})",
+                            object_config.name,
+                            MODSTAMP_FIELD,
+                            since.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                            encode_soql(filter));
+        let mut next_url = format!("/services/data/{}/query/?q={}", self.config.api_version, query);
+        let mut ids = vec!();
+        loop {
+            let req_builder = |uri: &String| format!("{}{}", uri, next_url);
+            let posted_str = self.client.get_resource(req_builder)?;
+            let v: Value = parse_json(&posted_str)?;
+            let records = v["records"].as_array().ok_or(SalesforceError::InvalidResponse("Error fetching unmatched records".to_owned()))?;
+            ids.extend(records.iter().filter_map(|record| record["Id"].as_str().map(|id| id.to_owned())));
+            match v["nextRecordsUrl"].as_str() {
+                Some(url) if !v["done"].as_bool().unwrap_or(true) => next_url = url.to_owned(),
+                _ => break,
+            }
+        }
+        Ok(ids)
+    }
+
+    //ids of records deleted since the given time
+    pub fn get_deleted_records(&self,
+                               object_config: &ObjectConfig,
+                               since: &DateTime<Utc>)
+                               -> Result<Vec<String>, SalesforceError> {
+        let end: DateTime<Utc> = Utc::now();
+        //deleted records are only available for the last 30 days
+        let start: DateTime<Utc> = max(*since, end.sub(Duration::days(DELETED_RETENTION_DAYS)));
+        let req_builder = |uri: &String| {
+            format!("{}/services/data/{}/sobjects/{}/deleted/?start={}&end={}",
+                    uri,
+                    self.config.api_version,
+                    object_config.name,
+                    start.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                    end.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        };
+        let posted_str = self.client.get_resource(req_builder)?;
+        let v: Value = parse_json(&posted_str)?;
+        let deleted = v["deletedRecords"]
+            .as_array()
+            .ok_or(SalesforceError::InvalidResponse("Error fetching deleted records".to_owned()))?
+            .iter()
+            .filter_map(|record| record["id"].as_str().map(|id| id.to_owned()))
+            .collect();
+        Ok(deleted)
     }
 
     pub fn get_records_from_describe(&self,
                                      describe: &SObjectConfiguration,
                                      object_name: &str)
-                                     -> Result<SObjectRowResultWrapper, String> {
+                                     -> Result<SObjectRowResultWrapper, SalesforceError> {
         let all_fields: Vec<String> = describe
             .get_fields()
             .iter()
             .map(|field| field.name.clone())
             .collect();
-        let query = format!("SELECT+{}+FROM+{}", all_fields.join(","), object_name);
+        let mut query = format!("SELECT+{}+FROM+{}", all_fields.join(","), object_name);
+        if let Some(filter) = describe.get_filter() {
+            query.push_str(format!("+WHERE+{}", encode_soql(filter)).as_str());
+        }
         //println!("{}",query);
         let req_builder = |uri: &String| format!("{}/services/data/{}/query/?q={}", 
                                                     uri, self.config.api_version, query);
-        let posted_str = try!(self.client
-                                  .get_resource(req_builder)
-                                  .map_err(|err| err.to_string()));
+        let posted_str = self.client.get_resource(req_builder)?;
         //println!("{}",posted_str);
-        let v: Value = serde_json::from_str(posted_str.as_str()).unwrap();
-        Ok(SObjectRowResultWrapper::new(&describe.get_name(), &describe.get_fields(), v))
+        let v: Value = parse_json(&posted_str)?;
+        Ok(SObjectRowResultWrapper::new(describe, v))
     }
 
     pub fn get_next_records(&self,
                             describe: &SObjectConfiguration,
                             wrapper: &SObjectRowResultWrapper)
-                            -> Option<SObjectRowResultWrapper> {
+                            -> Result<Option<SObjectRowResultWrapper>, SalesforceError> {
         if wrapper.done {
-            return None;
+            return Ok(None);
         }
         let req_builder = |uri: &String| format!("{}{}", uri, wrapper.next_url);
-        let posted_str = self.client
-            .get_resource(req_builder)
-            .map_err(|err| err.to_string());
-        match posted_str {
-            Ok(res) => {
-                let result: Value = serde_json::from_str(res.as_str()).unwrap();
-                return Some(SObjectRowResultWrapper::new(&describe.get_name(),
-                                                         &describe.get_fields(),
-                                                         result));
-            }
-            Err(_) => {
-                return None;
+        let posted_str = self.client.get_resource(req_builder)?;
+        let result: Value = parse_json(&posted_str)?;
+        Ok(Some(SObjectRowResultWrapper::new(describe, result)))
+    }
+
+    pub fn count_records(&self, object_name: &str, filter: Option<&String>) -> Result<u64, SalesforceError> {
+        let mut query = format!("SELECT+COUNT()+FROM+{}", object_name);
+        if let Some(filter) = filter {
+            query.push_str(format!("+WHERE+{}", encode_soql(filter)).as_str());
+        }
+        let req_builder = |uri: &String| format!("{}/services/data/{}/query/?q={}",
+                                                    uri, self.config.api_version, query);
+        let posted_str = self.client.get_resource(req_builder)?;
+        let v: Value = parse_json(&posted_str)?;
+        v["totalSize"].as_u64().ok_or(SalesforceError::InvalidResponse("Error counting records".to_owned()))
+    }
+
+    //decides between the REST query and a Bulk API 2.0 job for the initial load
+    //only the records matching the filter are loaded
+    pub fn use_bulk_query(&self, describe: &SObjectConfiguration) -> Result<bool, SalesforceError> {
+        match self.config.bulk_threshold {
+            Some(threshold) => Ok(self.count_records(describe.get_name(), describe.get_filter())? >= threshold),
+            None => Ok(false),
+        }
+    }
+
+    pub fn create_bulk_query(&self, describe: &SObjectConfiguration) -> Result<BulkQueryJob, SalesforceError> {
+        //compound and binary fields are not supported by bulk queries
+        let fields: Vec<String> = describe
+            .get_fields()
+            .iter()
+            .filter(|field| field.sf_type != "address" && field.sf_type != "location" && !field.is_blob())
+            .map(|field| field.name.clone())
+            .collect();
+        let mut query = format!("SELECT {} FROM {}", fields.join(","), describe.get_name());
+        if let Some(filter) = describe.get_filter() {
+            query.push_str(format!(" WHERE {}", filter).as_str());
+        }
+        let body = json!({
+            "operation": "query",
+            "query": query,
+        });
+        let req_builder = |uri: &String| format!("{}/services/data/{}/jobs/query", uri, self.config.api_version);
+        let posted_str = self.client.create_resource(body.to_string(), req_builder)?;
+        parse_json(&posted_str)
+    }
+
+    pub fn wait_for_bulk_query(&self, job: BulkQueryJob) -> Result<BulkQueryJob, SalesforceError> {
+        let mut job = job;
+        while !job.is_finished() {
+            sleep(StdDuration::from_millis(BULK_POLL_INTERVAL_MS));
+            let req_builder = |uri: &String| format!("{}/services/data/{}/jobs/query/{}",
+                                                        uri, self.config.api_version, job.id);
+            let posted_str = self.client.get_resource(req_builder)?;
+            job = parse_json(&posted_str)?;
+        }
+        if job.state != bulk::STATE_JOB_COMPLETE {
+            return Err(SalesforceError::InvalidResponse(format!("Bulk query {} {}: {}", job.id, job.state,
+                                                                job.error_message.unwrap_or(String::new()))));
+        }
+        Ok(job)
+    }
+
+    pub fn get_bulk_records(&self,
+                            describe: &SObjectConfiguration,
+                            job: &BulkQueryJob,
+                            locator: Option<&str>)
+                            -> Result<SObjectRowResultWrapper, SalesforceError> {
+        let req_builder = |uri: &String| {
+            let mut url = format!("{}/services/data/{}/jobs/query/{}/results?maxRecords={}",
+                                  uri, self.config.api_version, job.id, self.config.bulk_page_size);
+            if let Some(locator) = locator {
+                url.push_str(format!("&locator={}", locator).as_str());
             }
+            url
+        };
+        let (csv, next_locator) = self.client.get_resource_with_header(bulk::LOCATOR_HEADER, req_builder)?;
+        let v = bulk::csv_to_query_result(&csv, describe.get_fields(), &next_locator);
+        Ok(SObjectRowResultWrapper::new(describe, v))
+    }
+
+    pub fn get_next_bulk_records(&self,
+                                 describe: &SObjectConfiguration,
+                                 job: &BulkQueryJob,
+                                 wrapper: &SObjectRowResultWrapper)
+                                 -> Result<Option<SObjectRowResultWrapper>, SalesforceError> {
+        if wrapper.done {
+            return Ok(None);
         }
+        self.get_bulk_records(describe, job, Some(wrapper.next_url.as_str())).map(Some)
     }
 
     pub fn push_records(&self, object_type: &str, records: &[Record]) 
-        -> (HashMap<i32,String>, HashMap<i32,String>)
+        -> (HashMap<i32,String>, HashMap<i32,SalesforceError>)
     {
         let mut created_ids = HashMap::new();
         let mut failed_ids = HashMap::new();
-        for rec in records {
-            let sfid_opt = &rec.sfid;
-            let result = match sfid_opt {
-                &Some(ref sfid) => {
-                    let req_builder = |uri: &String| {
-                        format!("{}/services/data/{}/sobjects/{}/{}",
-                            uri, self.config.api_version, object_type, sfid
-                        )
-                    };
-                    self.client.update_resource(rec.to_json(), req_builder)
-                },
-                &None => {
-                    let req_builder = |uri: &String| {
-                        format!("{}/services/data/{}/sobjects/{}",
-                            uri, self.config.api_version, object_type
-                        )
-                    };
-                    self.client.create_resource(rec.to_json(), req_builder)
-                },
-            };
+        let (updates, creates): (Vec<&Record>, Vec<&Record>) = records
+            .iter()
+            .partition(|rec| rec.sfid.is_some());
+        for batch in creates.chunks(COLLECTION_BATCH_SIZE) {
+            let results = self.push_collection(object_type, batch, false);
+            for (rec, result) in batch.iter().zip(results.into_iter()) {
+                match result {
+                    Ok(id) => { created_ids.insert(rec.id, id); },
+                    Err(err) => { failed_ids.insert(rec.id, err); }
+                }
+            }
+        }
+        for batch in updates.chunks(COLLECTION_BATCH_SIZE) {
+            let results = self.push_collection(object_type, batch, true);
+            for (rec, result) in batch.iter().zip(results.into_iter()) {
+                if let Err(err) = result {
+                    failed_ids.insert(rec.id, err);
+                }
+            }
+        }
+        (created_ids, failed_ids)
+    }
 
-            match result {
-                Ok(json_result) => {
-                    //if record was created
-                    if sfid_opt.is_none() {
-                        println!("{:?}", json_result);
-                        let v: Value = serde_json::from_str(json_result.as_str()).unwrap();
-                        created_ids.insert(rec.id, v["id"].as_str().unwrap().to_string());
+    //deletes (id, sfid) pairs, returns the ids that could not be deleted
+    pub fn delete_records(&self, records: &[(i32, String)]) -> HashMap<i32, SalesforceError> {
+        let mut failed_ids = HashMap::new();
+        for batch in records.chunks(COLLECTION_BATCH_SIZE) {
+            let sfids: Vec<&str> = batch.iter().map(|&(_, ref sfid)| sfid.as_str()).collect();
+            let req_builder = |uri: &String| {
+                format!("{}/services/data/{}/composite/sobjects?ids={}&allOrNone=false",
+                        uri, self.config.api_version, sfids.join(","))
+            };
+            let results: Vec<Value> = match self.client.delete_resource(req_builder).and_then(|res| parse_json(&res)) {
+                Ok(results) => results,
+                Err(err) => {
+                    Event::error("Error deleting records").with_error(&err.to_string()).emit();
+                    for &(id, _) in batch {
+                        failed_ids.insert(id, err.clone());
+                    }
+                    continue;
+                }
+            };
+            for (i, &(id, _)) in batch.iter().enumerate() {
+                let res = match results.get(i) {
+                    Some(res) => res,
+                    None => {
+                        failed_ids.insert(id, SalesforceError::InvalidResponse("No result returned for record".to_owned()));
+                        continue;
                     }
-                },
-                Err(err_result) => {
-                    println!("{}", err_result);
-                    failed_ids.insert(rec.id, err_result);
+                };
+                if res["success"].as_bool() == Some(true) {
+                    continue;
                 }
+                let error = SalesforceError::from_record_errors(&res["errors"]);
+                //a retried delete may find the record removed by the first attempt
+                if error.error_code() == Some(errors::ENTITY_IS_DELETED) {
+                    continue;
+                }
+                failed_ids.insert(id, error);
             }
         }
-        (created_ids, failed_ids)
+        failed_ids
     }
+
+    //sends one sObject Collections request, returns one result per record in input order
+    fn push_collection(&self, object_type: &str, records: &[&Record], update: bool)
+        -> Vec<Result<String, SalesforceError>>
+    {
+        let body = json!({
+            "allOrNone": false,
+            "records": records.iter().map(|rec| rec.to_collection_value(object_type)).collect::<Vec<Value>>(),
+        });
+        let req_builder = |uri: &String| {
+            format!("{}/services/data/{}/composite/sobjects", uri, self.config.api_version)
+        };
+        let result = if update {
+            self.client.update_resource(body.to_string(), req_builder)
+        } else {
+            self.client.create_resource(body.to_string(), req_builder)
+        };
+        let results: Vec<Value> = match result.and_then(|res| parse_json(&res)) {
+            Ok(results) => results,
+            Err(err) => {
+                //the whole request failed, so did every record in it
+                Event::error("Error pushing records").with_object(object_type).with_error(&err.to_string()).emit();
+                return records.iter().map(|_| Err(err.clone())).collect();
+            }
+        };
+        records.iter()
+            .enumerate()
+            .map(|(i, rec)| {
+                let res = match results.get(i) {
+                    Some(res) => res,
+                    None => return Err(SalesforceError::InvalidResponse("No result returned for record".to_owned())),
+                };
+                if res["success"].as_bool().unwrap_or(false) {
+                    let id = res["id"].as_str()
+                        .map(|id| id.to_owned())
+                        .or(rec.sfid.clone())
+                        .unwrap_or(String::new());
+                    return Ok(id);
+                }
+                let err = SalesforceError::from_record_errors(&res["errors"]);
+                Event::warn("Record rejected").with_object(object_type).with_record(rec.id).with_error(&err.to_string()).emit();
+                Err(err)
+            })
+            .collect()
+    }
+}
+
+//responses that do not parse are reported as invalid instead of failing the sync
+fn parse_json<T>(body: &str) -> Result<T, SalesforceError>
+    where T: DeserializeOwned
+{
+    serde_json::from_str(body).map_err(|err| SalesforceError::InvalidResponse(err.to_string()))
+}
+
+fn and_filter(filter: Option<&String>) -> String {
+    match filter {
+        Some(filter) => format!("+AND+({})", encode_soql(filter)),
+        None => String::new(),
+    }
+}
+
+//configured soql conditions can contain quotes, spaces and operators
+fn encode_soql(soql: &str) -> String {
+    soql.bytes()
+        .map(|byte| match byte {
+            b' ' => "+".to_owned(),
+            b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ if byte.is_ascii_alphanumeric() => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
 }
@@ -1,5 +1,10 @@
 use serde_json::value::Value;
 use std::collections::HashMap;
+use std::rc::Rc;
+use chrono::prelude::*;
+use base64;
+use config::{CompoundMode, SyncDirection, ObjectSettings};
+use salesforce::compound;
 
 #[derive(Serialize, Deserialize)]
 pub struct SObjectList {
@@ -31,19 +36,41 @@ pub struct SObjectDescribe {
 pub trait SObjectConfiguration {
     fn get_name(&self) -> &String;
     fn get_fields(&self) -> &Vec<Field>;
+    fn get_compound_mode(&self) -> CompoundMode;
+    fn get_filter(&self) -> Option<&String>;
+    fn get_field_direction(&self, field_name: &str) -> SyncDirection;
 }
 
-impl SObjectConfiguration for SObjectDescribe {
+//an object that is being set up, the describe along with the settings from the config file
+pub struct SObjectSetup<'a> {
+    pub describe: &'a SObjectDescribe,
+    pub settings: &'a ObjectSettings,
+    pub compound_mode: CompoundMode,
+}
+
+impl<'a> SObjectConfiguration for SObjectSetup<'a> {
     fn get_name(&self) -> &String {
-        &self.name
+        &self.describe.name
     }
 
     fn get_fields(&self) -> &Vec<Field> {
-        &self.fields
+        &self.describe.fields
+    }
+
+    fn get_compound_mode(&self) -> CompoundMode {
+        self.compound_mode
+    }
+
+    fn get_filter(&self) -> Option<&String> {
+        self.settings.filter.as_ref()
+    }
+
+    fn get_field_direction(&self, field_name: &str) -> SyncDirection {
+        self.settings.direction.for_field(&self.settings.field_directions, field_name)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Field {
     pub name: String,
     pub length: u32,
@@ -51,61 +78,178 @@ pub struct Field {
     #[serde(rename = "type")]
     pub sf_type: String,
     pub updateable: bool,
+    #[serde(default)]
+    pub precision: u32,
+    #[serde(default)]
+    pub scale: u32,
+    //set on the components of address and location fields
+    #[serde(rename = "compoundFieldName", default)]
+    pub compound_field_name: Option<String>,
+}
+
+impl Field {
+    //queries return a link to the blob instead of its content, so the content is never synced
+    pub fn is_blob(&self) -> bool {
+        self.sf_type == "base64"
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Decimal(f64),
+    Text(String),
+    Date(NaiveDate),
+    DateTime(DateTime<Utc>),
+    Time(NaiveTime),
+    Binary(Vec<u8>),
+    List(Vec<String>),
+    Json(Value),
+}
+
+impl FieldValue {
+    //converts a json value according to the describe type of its field
+    pub fn from_json(field: &Field, value: &Value) -> FieldValue {
+        if value.is_null() {
+            return FieldValue::Null;
+        }
+        let text = match *value {
+            Value::String(ref val) => val.clone(),
+            Value::Object(_) | Value::Array(_) => return FieldValue::Json(value.clone()),
+            ref val => val.to_string(),
+        };
+        let typed = match field.sf_type.as_str() {
+            "boolean" => value.as_bool().or(text.parse::<bool>().ok()).map(FieldValue::Bool),
+            "int" | "long" => value.as_i64().or(text.parse::<i64>().ok()).map(FieldValue::Integer),
+            "double" | "currency" | "percent" => {
+                value.as_f64().or(text.parse::<f64>().ok()).map(FieldValue::Decimal)
+            },
+            "date" => NaiveDate::parse_from_str(&text, "%Y-%m-%d").ok().map(FieldValue::Date),
+            "datetime" => parse_datetime(&text).map(FieldValue::DateTime),
+            "time" => NaiveTime::parse_from_str(&text, "%H:%M:%S%.fZ")
+                .or(NaiveTime::parse_from_str(&text, "%H:%M:%S%.f"))
+                .ok()
+                .map(FieldValue::Time),
+            "multipicklist" => Some(FieldValue::List(text.split(';').map(|val| val.to_owned()).collect())),
+            "base64" => Some(base64::decode(&text).map(FieldValue::Binary).unwrap_or(FieldValue::Null)),
+            _ => None,
+        };
+        typed.unwrap_or(FieldValue::Text(text))
+    }
+
+    pub fn to_text(&self) -> Option<String> {
+        match *self {
+            FieldValue::Null => None,
+            FieldValue::Bool(val) => Some(val.to_string()),
+            FieldValue::Integer(val) => Some(val.to_string()),
+            FieldValue::Decimal(val) => Some(val.to_string()),
+            FieldValue::Text(ref val) => Some(val.clone()),
+            FieldValue::Date(ref val) => Some(val.format("%Y-%m-%d").to_string()),
+            FieldValue::DateTime(ref val) => Some(val.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()),
+            FieldValue::Time(ref val) => Some(val.format("%H:%M:%S%.3fZ").to_string()),
+            FieldValue::Binary(ref val) => Some(base64::encode(val)),
+            FieldValue::List(ref val) => Some(val.join(";")),
+            FieldValue::Json(ref val) => Some(val.to_string()),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match *self {
+            FieldValue::Null => Value::Null,
+            FieldValue::Bool(val) => json!(val),
+            FieldValue::Integer(val) => json!(val),
+            FieldValue::Decimal(val) => json!(val),
+            FieldValue::Json(ref val) => val.clone(),
+            _ => json!(self.to_text()),
+        }
+    }
+}
+
+//salesforce returns 2018-01-01T10:00:00.000+0000, bulk results use 2018-01-01T10:00:00.000Z
+pub fn parse_datetime(val: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(val, "%Y-%m-%dT%H:%M:%S%.f%z")
+        .or(DateTime::parse_from_rfc3339(val))
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+}
+
+#[derive(Debug)]
+pub struct ColumnValue {
+    pub column: String,
+    pub field: Rc<Field>,
+    pub value: FieldValue,
 }
 
 pub struct SObjectRowResultWrapper {
-    pub rows: HashMap<String, (Vec<String>, Vec<String>)>,
+    pub rows: HashMap<String, Vec<ColumnValue>>,
     pub object_name: String,
     pub next_url: String,
     pub done: bool,
+    pub last_modstamp: Option<DateTime<Utc>>,
+    //SystemModstamp of each record, keyed by id
+    pub modstamps: HashMap<String, DateTime<Utc>>,
 }
 
 impl SObjectRowResultWrapper {
-    pub fn new(name: &String,
-               fields: &Vec<Field>,
+    pub fn new(object: &SObjectConfiguration,
                describe_result: Value)
                -> SObjectRowResultWrapper {
         let rows_raw = describe_result["records"].as_array().unwrap();
-        let mut result: HashMap<String, (Vec<String>, Vec<String>)> = HashMap::new();
+        let mut result: HashMap<String, Vec<ColumnValue>> = HashMap::new();
+        let mut last_modstamp: Option<DateTime<Utc>> = None;
+        let mut modstamps: HashMap<String, DateTime<Utc>> = HashMap::new();
+        let all_fields = object.get_fields();
+        let fields: Vec<Rc<Field>> = compound::column_fields(all_fields, object.get_compound_mode())
+            .into_iter()
+            //columns written only from postgres are left untouched
+            .filter(|field| field.name == "Id" || object.get_field_direction(&field.name).pulls())
+            .map(|field| Rc::new(field.clone()))
+            .collect();
         for row in rows_raw {
-            let mut field_names: Vec<String> = Vec::new();
-            let mut field_values: Vec<String> = Vec::new();
-
-            for field in fields {
-                //filter compound address type
-                if field.sf_type == "address" {
-                    continue;
-                }
-
-                if field.name == "Id" {
-                    field_names.push("sfid".to_owned());
-                } else {
-                    field_names.push(field.name.to_lowercase().clone());
-                }
-                let value = &row[&field.name];
-                match value {
-                    //add single quotes for Strings
-                    &Value::String(ref val) => {
-                        let mut str_val = String::new();
-                        str_val.push_str("'");
-                        str_val.push_str(val.as_str());
-                        str_val.push_str("'");
-                        field_values.push(str_val);
-                    }
-                    _ => field_values.push(value.to_string().clone()),
-                }
+            let modstamp = row["SystemModstamp"]
+                .as_str()
+                .and_then(parse_datetime);
+            if modstamp > last_modstamp {
+                last_modstamp = modstamp;
             }
+
+            let columns: Vec<ColumnValue> = fields.iter()
+                .map(|field| {
+                    let column = if field.name == "Id" {
+                        "sfid".to_owned()
+                    } else {
+                        field.name.to_lowercase()
+                    };
+                    let value = if compound::is_compound(field) {
+                        FieldValue::from_json(field, &compound::assemble(field, all_fields, row))
+                    } else {
+                        FieldValue::from_json(field, &row[&field.name])
+                    };
+                    ColumnValue {
+                        column: column,
+                        field: field.clone(),
+                        value: value,
+                    }
+                })
+                .collect();
             let id = row["Id"].as_str().unwrap().to_owned();
-            result.insert(id, (field_names, field_values));
+            if let Some(modstamp) = modstamp {
+                modstamps.insert(id.clone(), modstamp);
+            }
+            result.insert(id, columns);
         }
         SObjectRowResultWrapper {
             rows: result,
-            object_name: name.clone(),
+            object_name: object.get_name().clone(),
             next_url: describe_result["nextRecordsUrl"]
                 .as_str()
                 .unwrap_or("")
                 .to_string(),
             done: describe_result["done"].as_bool().unwrap_or(false),
+            last_modstamp: last_modstamp,
+            modstamps: modstamps,
         }
     }
 }
@@ -2,9 +2,16 @@ use std::sync::{Mutex, Arc};
 use db::Db;
 use salesforce::Salesforce;
 use config::SyncConfig;
-use std::sync::mpsc::{Sender};
+use logging::{Event, EventSender};
+use metrics;
+use salesforce::limits::Throttle;
+use std::time::Instant;
 use std::collections::HashMap;
 use sync::executer::ExecuterInner;
+use db::objects::ObjectConfig;
+use db::record::Record;
+use db::conflict::{self, Resolution};
+use salesforce::objects::parse_datetime;
 
 pub struct ExecuterInnerDB {
     db: Arc<Db>,
@@ -23,37 +30,194 @@ impl ExecuterInnerDB {
             config: config,
         }
     }
+
+    //drops records that lost against a newer salesforce version or were parked
+    fn check_conflicts(&self, config: &ObjectConfig, records: Vec<Record>, sender: &EventSender) -> Vec<Record> {
+        let is_resolved = |rec: &Record| rec.state.as_ref().map(|state| state.as_str()) == Some(conflict::STATE_RESOLVED);
+        let records: Vec<Record> = records.into_iter()
+            .filter(|rec| rec.state.as_ref().map(|state| state.as_str()) != Some(conflict::STATE_CONFLICT))
+            //nothing left to push, the row was overwritten by a pull in the meantime
+            .filter(|rec| rec.sfid.is_none() || rec.modified.is_some() || is_resolved(rec))
+            .collect();
+        let sfids: Vec<String> = records.iter()
+            .filter(|rec| !is_resolved(rec))
+            .filter_map(|rec| rec.sfid.clone())
+            .collect();
+        if sfids.is_empty() {
+            return records;
+        }
+        let versions = match self.salesforce.get_current_versions(config, &sfids) {
+            Ok(versions) => versions,
+            Err(err) => {
+                sender.send(Event::error("Error checking conflicts").with_object(&config.name).with_error(&err.to_string()));
+                metrics::count_error("db_executer", &config.name);
+                for rec in records.iter().filter(|rec| rec.sfid.is_some()) {
+                    self.db.set_error_state(&config.name, &rec.id, &err, &self.config.retry);
+                }
+                return records.into_iter().filter(|rec| rec.sfid.is_none()).collect();
+            }
+        };
+        records.into_iter()
+            .filter(|rec| {
+                let version = match rec.sfid.as_ref().and_then(|sfid| versions.get(sfid)) {
+                    Some(version) => version,
+                    None => return true,
+                };
+                let modstamp = version["SystemModstamp"].as_str().and_then(parse_datetime);
+                //rows never pulled with a SystemModstamp have no baseline to compare against
+                if rec.sf_modstamp.is_none() || !conflict::sf_changed(rec.sf_modstamp, modstamp.as_ref()) {
+                    return true;
+                }
+                match conflict::resolve(config.conflict_policy, rec.modified, modstamp.as_ref()) {
+                    Resolution::KeepDb => true,
+                    Resolution::TakeSalesforce => false,
+                    Resolution::Park => {
+                        let _ = self.db.park_conflict(&config.name, &rec.id, version)
+                            .map_err(|err| sender.send(Event::error("Error parking conflict").with_object(&config.name)
+                                .with_record(rec.id).with_error(&err)));
+                        sender.send(Event::warn("Conflict parked").with_object(&config.name).with_record(rec.id));
+                        false
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn update_metrics(&self, sender: &EventSender) {
+        match self.db.count_pending_outbox() {
+            Ok(depth) => metrics::set_gauge(metrics::OUTBOX_DEPTH, &[], depth as f64),
+            Err(err) => sender.send(Event::error("Error counting outbox entries").with_error(&err)),
+        }
+        match self.db.count_error_rows() {
+            Ok(counts) => {
+                for (name, count) in counts {
+                    metrics::set_gauge(metrics::ERROR_ROWS, &[("object", &name)], count as f64);
+                }
+            },
+            Err(err) => sender.send(Event::error("Error counting error rows").with_error(&err)),
+        }
+    }
 }
 
 impl ExecuterInner for ExecuterInnerDB{
-    fn execute(&self, sender: Sender<String>) {
-        //let _ = sender.send("Executer DB".to_owned());
+    fn execute(&self, sender: EventSender) {
+        let started = Instant::now();
+        if self.salesforce.check_budget() == Throttle::Pause {
+            sender.send(Event::warn("Api budget used up, skipping cycle"));
+            return;
+        }
         let mut records_map: HashMap<String, Vec<i32>> = HashMap::new();
-        for note in self.db.get_notifications().iter() {
-            let object: Vec<&str> = note.split("::").collect();
-            //println!("{}",note);
-            let _ = sender.send(note.clone());
-            let name = object[0].to_owned();
-            let _name = name.clone();
-            let id = object[1].parse::<i32>().unwrap();
-            if !records_map.contains_key(&name){
-                records_map.insert(name,vec!() );
+        let mut deleted_map: HashMap<String, Vec<(i32, String)>> = HashMap::new();
+        //notifications only wake the executer up, the outbox holds the changes
+        let _ = self.db.get_notifications();
+        match self.db.schedule_retries() {
+            Ok(0) => {},
+            Ok(count) => sender.send(Event::info("Retrying failed rows").with_count(count)),
+            Err(err) => sender.send(Event::error("Error scheduling retries").with_error(&err)),
+        }
+        let entries = match self.db.claim_outbox(self.config.outbox_batch_size, self.config.outbox_lease_secs) {
+            Ok(entries) => entries,
+            Err(err) => {
+                sender.send(Event::error("Error claiming outbox entries").with_error(&err));
+                metrics::count_error("db_executer", metrics::GLOBAL_OBJECT);
+                return;
+            }
+        };
+        for entry in &entries {
+            sender.send(Event::debug(&entry.op).with_object(&entry.table_name).with_record(entry.record_id));
+            let name = entry.table_name.clone();
+            if entry.is_delete() {
+                //rows never pushed to salesforce have nothing to delete there
+                match entry.sfid {
+                    Some(ref sfid) if !sfid.is_empty() => {
+                        deleted_map.entry(name).or_insert(vec!()).push((entry.record_id, sfid.clone()));
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+            let ids = records_map.entry(name).or_insert(vec!());
+            if !ids.contains(&entry.record_id) {
+                ids.push(entry.record_id);
             }
-            records_map.get_mut(&_name).unwrap().push(id);
         }
         //println!("{:?}", records_map);
+        let mut configs: HashMap<String, ObjectConfig> = HashMap::new();
+        let mut unloaded: Vec<String> = vec!();
+        for key in records_map.keys().chain(deleted_map.keys()) {
+            if configs.contains_key(key) || unloaded.contains(key) {
+                continue;
+            }
+            match self.db.get_object_config(key) {
+                Ok(config) => { configs.insert(key.clone(), config); },
+                Err(err) => {
+                    sender.send(Event::error("Error loading object config").with_object(key).with_error(&err));
+                    metrics::count_error("db_executer", key);
+                    unloaded.push(key.clone());
+                }
+            }
+        }
         for key in records_map.keys() {
-            let records = self.db.get_object_data_by_id(&key, records_map.get::<str>(&key).unwrap());
+            //objects only read from salesforce are never pushed
+            let config = match configs.get(key) {
+                Some(config) if config.pushes_any() => config,
+                _ => continue,
+            };
+            let records = self.db.get_object_data_by_id(config, records_map.get::<str>(&key).unwrap());
+            let records = self.check_conflicts(config, records, &sender);
             for rec in &records{
-                println!("{}", rec.to_json());
+                sender.send(Event::debug(&rec.to_json()).with_object(key).with_record(rec.id));
             }
             let ids = self.salesforce.push_records(&key, &records);
             self.db.update_ids(&key, &ids.0);
             for (err_id, error) in &ids.1 {
-                self.db.set_error_state(&key, err_id, &error);
+                self.db.set_error_state(&key, err_id, error, &self.config.retry);
+            }
+            let pushed: Vec<i32> = records.iter()
+                .map(|rec| rec.id)
+                .filter(|id| !ids.1.contains_key(id))
+                .collect();
+            let _ = self.db.mark_pushed(&key, &pushed)
+                .map_err(|err| sender.send(Event::error("Error marking rows pushed").with_object(key).with_error(&err)));
+            sender.send(Event::info("Pushed rows").with_object(key).with_count(pushed.len() as u64));
+            metrics::inc_counter(metrics::RECORDS_PUSHED, &[("object", key)], pushed.len() as u64);
+            if !ids.1.is_empty() {
+                sender.send(Event::warn("Rows failed to push").with_object(key).with_count(ids.1.len() as u64));
+                metrics::inc_counter(metrics::ERRORS, &[("executer", "db_executer"), ("object", key)], ids.1.len() as u64);
+            }
+        }
+        for (key, deleted) in &deleted_map {
+            match configs.get(key) {
+                Some(config) if config.direction.pushes() => {},
+                _ => continue,
+            }
+            let failed_ids = self.salesforce.delete_records(deleted);
+            for &(ref id, ref sfid) in deleted {
+                if let Some(error) = failed_ids.get(id) {
+                    self.db.log_failed_delete(key, id, sfid, error, &self.config.retry);
+                }
+            }
+            let deleted_ids: Vec<i32> = deleted.iter()
+                .map(|&(id, _)| id)
+                .filter(|id| !failed_ids.contains_key(id))
+                .collect();
+            let _ = self.db.clear_failed_deletes(key, &deleted_ids)
+                .map_err(|err| sender.send(Event::error("Error clearing failed deletes").with_object(key).with_error(&err)));
+            sender.send(Event::info("Deleted records").with_object(key).with_count(deleted_ids.len() as u64));
+            if !failed_ids.is_empty() {
+                sender.send(Event::warn("Records failed to delete").with_object(key).with_count(failed_ids.len() as u64));
+                metrics::inc_counter(metrics::ERRORS, &[("executer", "db_executer"), ("object", key)], failed_ids.len() as u64);
             }
-            println!("{:?}", ids.0);
         }
+        //failed pushes are kept in the row state, entries of objects without a config are left to the lease
+        let entry_ids: Vec<i64> = entries.iter()
+            .filter(|entry| !unloaded.contains(&entry.table_name))
+            .map(|entry| entry.id)
+            .collect();
+        let _ = self.db.complete_outbox(&entry_ids)
+            .map_err(|err| sender.send(Event::error("Error completing outbox entries").with_error(&err)));
+        self.update_metrics(&sender);
+        metrics::set_gauge(metrics::CYCLE_DURATION, &[("executer", "db_executer")], metrics::seconds(started.elapsed()));
     }
     
     fn start(&self) {
@@ -71,7 +235,7 @@ impl ExecuterInner for ExecuterInnerDB{
     }
 
     fn get_timeout(&self) -> u64 {
-        self.config.timeout
+        self.salesforce.get_throttled_timeout(self.config.timeout)
     }
 }
 
@@ -1,15 +1,22 @@
 use std::sync::{Mutex, Arc};
 use db::Db;
 use salesforce::Salesforce;
-use config::SyncConfig;
-use std::sync::mpsc::{Sender};
+use config::{SyncConfig, UnmatchedPolicy};
+use logging::{Event, EventSender};
+use metrics;
+use salesforce::limits::Throttle;
 use db::objects::ObjectConfig;
+use salesforce::objects::SObjectRowResultWrapper;
+use chrono::prelude::*;
 use sync::executer::ExecuterInner;
+use sync::schema;
+use std::time::{Duration, Instant};
 
 pub struct ExecuterInnerSF {
     db: Arc<Db>,
     salesforce: Arc<Salesforce>,
     synch_switch: Arc<Mutex<bool>>,
+    last_schema_check: Mutex<Option<Instant>>,
     pub config: &'static SyncConfig,
 }
 
@@ -20,42 +27,163 @@ impl ExecuterInnerSF {
             db: db,
             salesforce: salesforce,
             synch_switch: Arc::new(Mutex::new(false)),
+            last_schema_check: Mutex::new(None),
             config: config,
         }
     }
+
+    //upserts every page changed since the given time, advancing the watermark page by page
+    fn sync_object(&self, object: &ObjectConfig, since: &DateTime<Utc>, sender: &EventSender)
+        -> Result<u64, String> {
+        let row_result = self.salesforce.get_last_updated_records(object, since).map_err(|err| err.to_string())?;
+        sender.send(Event::debug("Rows to synch").with_object(&object.name).with_count(row_result.rows.len() as u64));
+        let mut row_count = self.db.upsert_object_rows(object, &row_result)?;
+        self.advance_watermark(object, &row_result);
+        let mut next_wrapper_opt = self.salesforce.get_next_records(object, &row_result)
+            .map_err(|err| err.to_string())?;
+        while let Some(next_wrapper) = next_wrapper_opt {
+            row_count += self.db.upsert_object_rows(object, &next_wrapper)?;
+            self.advance_watermark(object, &next_wrapper);
+            sender.send(Event::debug("Synched rows").with_object(&object.name).with_count(row_count));
+            if !next_wrapper.done {
+                sender.send(Event::debug(&format!("Next Path: {}", next_wrapper.next_url)).with_object(&object.name));
+            }
+            next_wrapper_opt = self.salesforce.get_next_records(object, &next_wrapper)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(row_count)
+    }
+
+    fn is_schema_check_due(&self) -> bool {
+        let mut last_check = self.last_schema_check.lock().unwrap();
+        let interval = Duration::from_secs(self.config.schema_check_interval);
+        match *last_check {
+            Some(instant) if instant.elapsed() < interval => false,
+            _ => {
+                *last_check = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    fn reconcile_schemas(&self, sender: &EventSender) {
+        let objects: Vec<ObjectConfig> = match self.db.get_selected_objects(-1) {
+            Ok(objects) => objects,
+            Err(err) => {
+                sender.send(Event::error("Error loading objects").with_error(&err));
+                metrics::count_error("sf_executer", metrics::GLOBAL_OBJECT);
+                return;
+            }
+        };
+        for object in &objects {
+            match schema::reconcile_object(&self.db, &self.salesforce, object, self.config.drop_removed_columns) {
+                Ok(ref changes) if !changes.is_empty() => {
+                    sender.send(Event::info("Schema changed").with_object(&object.name).with_count(changes.len() as u64));
+                },
+                Ok(_) => {},
+                Err(err) => {
+                    sender.send(Event::error("Error reconciling schema").with_object(&object.name).with_error(&err));
+                    metrics::count_error("sf_executer", &object.name);
+                }
+            }
+        }
+    }
+
+    //records that were changed so they no longer match the filter are removed locally
+    fn purge_unmatched(&self, object: &ObjectConfig, since: &DateTime<Utc>, sender: &EventSender) {
+        match self.salesforce.get_unmatched_records(object, since) {
+            Ok(unmatched) => {
+                let purged_count = self.db.purge_rows(object, &unmatched)
+                    .map_err(|err| sender.send(Event::error("Error purging rows").with_object(&object.name).with_error(&err)))
+                    .unwrap_or(0);
+                sender.send(Event::info("Purged rows no longer matching the filter").with_object(&object.name).with_count(purged_count));
+            },
+            Err(err) => {
+                sender.send(Event::error("Error fetching unmatched records").with_object(&object.name).with_error(&err.to_string()));
+                metrics::count_error("sf_executer", &object.name);
+            }
+        }
+    }
+
+    fn update_watermark_metrics(&self, sender: &EventSender) {
+        match self.db.get_watermarks() {
+            Ok(watermarks) => {
+                for (name, watermark) in watermarks {
+                    if let Some(watermark) = watermark {
+                        metrics::set_watermark(&name, DateTime::<Utc>::from_utc(watermark, Utc));
+                    }
+                }
+            },
+            Err(err) => sender.send(Event::error("Error reading watermarks").with_error(&err)),
+        }
+    }
+
+    fn advance_watermark(&self, object: &ObjectConfig, wrapper: &SObjectRowResultWrapper) {
+        if let Some(ref modstamp) = wrapper.last_modstamp {
+            self.db.update_watermark(object.id, modstamp);
+        }
+    }
 }
 
 impl ExecuterInner for ExecuterInnerSF {
 
-    fn execute(&self, sender: Sender<String>) {
+    fn execute(&self, sender: EventSender) {
         //println!("executing.... ");
-        let objects: Vec<ObjectConfig> = self.db.get_selected_objects(1).unwrap();
+        let started = Instant::now();
+        if self.salesforce.check_budget() == Throttle::Pause {
+            sender.send(Event::warn("Api budget used up, skipping cycle"));
+            return;
+        }
+        if self.is_schema_check_due() {
+            self.reconcile_schemas(&sender);
+        }
+        let objects: Vec<ObjectConfig> = match self.db.get_selected_objects(1) {
+            Ok(objects) => objects,
+            Err(err) => {
+                sender.send(Event::error("Error loading objects").with_error(&err));
+                metrics::count_error("sf_executer", metrics::GLOBAL_OBJECT);
+                return;
+            }
+        };
         for i in 0..objects.len() {
+            //objects only written from postgres are never pulled
+            if !objects[i].pulls_any() {
+                continue;
+            }
             let fields = objects[i].get_field_names();
-            let _ = sender.send(format!("{} {} {:?}", i + 1, objects[i].name, fields.len()));
-            let row_result = self.salesforce
-                .get_last_updated_records(&objects[i], 1)
-                .unwrap();
-            let _ = sender.send(format!("num rows to synch: {}", row_result.rows.len()));
-            let result = self.db
-                .upsert_object_rows(&row_result)
-                .map_err(|err| println!("{}", err));
-            let mut row_count = result.unwrap();
-            let mut next_wrapper_opt = self.salesforce.get_next_records(&objects[i], &row_result);
-            while let Some(next_wrapper) = next_wrapper_opt {
-                row_count += self.db.populate(&next_wrapper).unwrap();
-                let _ = sender.send(format!("Synched {} rows", row_count));
-                if !next_wrapper.done {
-                    let _ = sender.send(format!("Next Path: {}", next_wrapper.next_url));
-                } else {
-                    let _ = sender.send(format!("Done: {} rows", row_count));
+            sender.send(Event::debug("Pulling fields").with_object(&objects[i].name).with_count(fields.len() as u64));
+            let since = objects[i].get_sync_start(self.config.overlap_secs);
+            match self.sync_object(&objects[i], &since, &sender) {
+                Ok(row_count) => {
+                    sender.send(Event::info("Pulled rows").with_object(&objects[i].name).with_count(row_count));
+                    metrics::inc_counter(metrics::RECORDS_PULLED, &[("object", &objects[i].name)], row_count);
+                },
+                Err(err) => {
+                    //watermark is left untouched, the next cycle retries the same window
+                    sender.send(Event::error("Error syncing").with_object(&objects[i].name).with_error(&err));
+                    metrics::count_error("sf_executer", &objects[i].name);
+                    continue;
                 }
-                next_wrapper_opt = self.salesforce.get_next_records(&objects[i], &next_wrapper);
             }
-
-            let _ = sender.send(format!("{}", result.unwrap()));
+            match self.salesforce.get_deleted_records(&objects[i], &since) {
+                Ok(deleted) => {
+                    let deleted_count = self.db.delete_rows(&objects[i], &deleted)
+                        .map_err(|err| sender.send(Event::error("Error deleting rows").with_object(&objects[i].name).with_error(&err)))
+                        .unwrap_or(0);
+                    sender.send(Event::info("Deleted rows").with_object(&objects[i].name).with_count(deleted_count));
+                },
+                Err(err) => {
+                    sender.send(Event::error("Error fetching deleted records").with_object(&objects[i].name).with_error(&err.to_string()));
+                    metrics::count_error("sf_executer", &objects[i].name);
+                }
+            }
+            if objects[i].unmatched_policy == UnmatchedPolicy::Purge {
+                self.purge_unmatched(&objects[i], &since, &sender);
+            }
             self.db.update_last_sync_time(objects[i].id);
         }
+        self.update_watermark_metrics(&sender);
+        metrics::set_gauge(metrics::CYCLE_DURATION, &[("executer", "sf_executer")], metrics::seconds(started.elapsed()));
     }
 
     fn start(&self) {
@@ -71,8 +199,6 @@ impl ExecuterInner for ExecuterInnerSF {
     }
 
     fn get_timeout(&self) -> u64 {
-        self.config.timeout
+        self.salesforce.get_throttled_timeout(self.config.timeout)
     }
 }
-
-
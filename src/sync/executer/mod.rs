@@ -1,20 +1,26 @@
 pub mod executer_sf;
 pub mod executer_db;
 
-use std::sync::{Mutex, Arc};
+use std::sync::Arc;
 use db::Db;
 use salesforce::Salesforce;
-use std::thread::{self, sleep};
-use std::time::Duration;
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
 use std::fmt::{Display, Formatter, Result};
 use config::SyncConfig;
-use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
 use sync::executer::executer_sf::ExecuterInnerSF;
 use sync::executer::executer_db::ExecuterInnerDB;
+use logging::{self, Event, EventSender};
+
+//steps of the pause between two cycles, a stop request is noticed after at most one step
+const STOP_POLL_MS: u64 = 200;
 
 pub struct Executer  {
     inners: Vec<Arc<EIW>>,
-    pub receiver: Option<Arc<Mutex<Receiver<String>>>>,
+    workers: Vec<JoinHandle<u32>>,
+    done: Option<Receiver<()>>,
+    config: &'static SyncConfig,
 }
 
 impl Executer {
@@ -23,54 +29,86 @@ impl Executer {
         let inner_db = EIW::DB(ExecuterInnerDB::new(salesforce,db,config));
         Executer {
             inners: vec!(Arc::new(inner_sf), Arc::new(inner_db)),
-            receiver: None,
+            workers: vec!(),
+            done: None,
+            config: config,
         }
     }
 
     pub fn start_sync(&mut self) {
-        let (send, recv) = channel::<String>();
-        self.receiver = Some(Arc::new(Mutex::new(recv)));
+        let (done_send, done_recv) = channel::<()>();
+        self.done = Some(done_recv);
         for val in self.inners.iter() {
             {     
                 val.convert().start();
             }
             let val = val.clone();
-            let send = send.clone();
-            thread::spawn(move ||{
+            let send = logging::sender().for_executer(&val.to_string());
+            let done = done_send.clone();
+            let worker = thread::spawn(move ||{
                 let local_self = val.convert();
-                for i in 1.. {  
+                let mut loops = 0;
+                //a cycle that is running when the sync is stopped is finished first
+                while local_self.is_running() {
+                    loops += 1;
                     local_self.execute(send.clone());
-                    {
-                        let data = local_self.is_running();
-                        if !data {
-                            let _ = send.send(format!("Stopped Thread after {} loops", i));
-                            return 0;
-                        }
-                        let _ = send.send(
-                            format!("tick: {}, type: {}",
-                                              i,
-                                              val)
-                        );
-                    }    
-                    
-                    sleep(Duration::from_millis(local_self.get_timeout()));
+                    send.send(Event::debug(&format!("tick: {}", loops)));
+                    let started = Instant::now();
+                    let timeout = Duration::from_millis(local_self.get_timeout());
+                    while local_self.is_running() && started.elapsed() < timeout {
+                        sleep(Duration::from_millis(STOP_POLL_MS));
+                    }
                 }
-                return 0;
-            });    
+                send.send(Event::info("Stopped Thread").with_count(loops as u64));
+                let _ = done.send(());
+                loops
+            });
+            self.workers.push(worker);
+        }
+    }
+
+    //a single pull and push cycle in the calling thread
+    pub fn run_once(&self, sender: EventSender) {
+        for val in self.inners.iter() {
+            val.convert().execute(sender.for_executer(&val.to_string()));
         }
     }
 
-    pub fn stop_sync(&mut self) {
+    //stops the workers and joins them, false if they were still busy after the shutdown timeout
+    pub fn stop_sync(&mut self) -> bool {
         for val in self.inners.iter() {
             val.convert().stop();
         }
-        self.receiver = None;
+        let done = match self.done.take() {
+            Some(done) => done,
+            None => return true,
+        };
+        let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        let started = Instant::now();
+        for _ in 0..self.workers.len() {
+            let elapsed = started.elapsed();
+            let remaining = if elapsed < timeout { timeout - elapsed } else { Duration::from_secs(0) };
+            match done.recv_timeout(remaining) {
+                Ok(_) => {},
+                //a worker that panicked never reports back
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    //the remaining workers are left detached
+                    self.workers.clear();
+                    return false;
+                }
+            }
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        true
     }
 
 }
 
 pub trait ExecuterInner {
-    fn execute(&self, Sender<String>);
+    fn execute(&self, EventSender);
     fn get_timeout(&self) -> u64;
     fn start(&self);
     fn is_running(&self) -> bool;
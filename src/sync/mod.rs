@@ -1,6 +1,9 @@
 pub mod executer;
 pub mod setup;
-pub mod logger;
+pub mod plan;
+pub mod schema;
+pub mod selection;
+pub mod shutdown;
 
 use std::io;
 use config::Config;
@@ -11,8 +14,8 @@ use db::Db;
 use std::sync::Arc;
 use sync::executer::Executer;
 use sync::setup::Setup;
-use std::cell::RefCell;
-use sync::logger::Logger;
+use logging;
+use db::dead_letter::DeadLetter;
 
 const STATE_START: u8 = 0;
 const STATE_SETUP: u8 = 49;
@@ -20,6 +23,7 @@ const STATE_SYNC: u8 = 50;
 const STATE_EXIT: u8 = 51;
 const STATE_LIST_OBJECTS: u8 = 52;
 const STATE_SELECTED_OBJECTS: u8 = 53;
+const STATE_FAILED_ROWS: u8 = 54;
 const STATE_START_SYNC: u8 = 49;
 const STATE_STOP_SYNC: u8 = 50;
 const STATE_SYNC_STATUS: u8 = 51;
@@ -30,29 +34,29 @@ pub struct Sync {
     input: String,
     executer: Executer,
     setup: Setup,
-    logger: RefCell<Logger>,
     config: &'static Config,
 }
 
 
 impl Sync {
-    pub fn new(config: &'static Config) -> Sync {
-        let sf = Salesforce::new(&config.salesforce);
-        let db_arc = Arc::new(Db::new(&config.db));
+    pub fn new(config: &'static Config) -> Result<Sync, String> {
+        let sf = Salesforce::new(&config.salesforce).map_err(|err| err.to_string())?;
+        let db_arc = Arc::new(Db::new(&config.db)?);
         let sf_arc = Arc::new(sf);
-        Sync {
+        Ok(Sync {
             level: STATE_START,
             command: STATE_START,
             input: String::new(),
             executer: Executer::new(db_arc.clone(), sf_arc.clone(), &config.sync),
-            setup: Setup::new(db_arc, sf_arc),
-            logger: RefCell::new(Logger::new()),
+            setup: Setup::new(db_arc, sf_arc, &config.sync),
             config: config,
-        }
+        })
     }
     
     pub fn run(&mut self) {
         let mut input = String::new();
+        //the menu owns the console until the status is shown
+        logging::pause_console();
 
         loop {
             match *self {
@@ -61,6 +65,7 @@ impl Sync {
                 Sync {level: STATE_START, command: STATE_SYNC,  ..} => self.sync(),
                 Sync {level: STATE_SETUP, command: STATE_LIST_OBJECTS, ..} => self.list(),
                 Sync {level: STATE_SETUP, command: STATE_SELECTED_OBJECTS, ..} => self.show_selected_objects(),
+                Sync {level: STATE_SETUP, command: STATE_FAILED_ROWS, ..} => self.show_failed_rows(),
                 Sync {level: STATE_SYNC, command: STATE_START_SYNC, ..} => self.start_sync(),
                 Sync {level: STATE_SYNC, command: STATE_STOP_SYNC, ..} => self.stop_sync(),
                 Sync {level: STATE_SYNC, command: STATE_SYNC_STATUS, ..} => self.start_show_log(),
@@ -79,6 +84,9 @@ impl Sync {
                     self.delete_object();
                     println!("Deleted Object: {}", self.command);
                 }
+                Sync { level: STATE_FAILED_ROWS, .. } => {
+                    self.handle_failed_rows();
+                }
                 _ => {
                     self.start();
                     println!("Error: {}", self.level);
@@ -114,6 +122,7 @@ impl Sync {
         println!("Setup:");
         println!("4. List available Objects");
         println!("5. Show synchronized Objects");
+        println!("6. Show failed rows");
     }
     
     fn sync(&self) {
@@ -130,27 +139,19 @@ impl Sync {
 
     fn stop_sync(&mut self) {
         println!("Stopping ... ");
-        self.executer.stop_sync();
+        if !self.executer.stop_sync() {
+            println!("Sync did not stop within {} seconds", self.config.sync.shutdown_timeout_secs);
+        }
     }
 
+    //events are kept while the menu is shown and printed once the status is opened
     fn start_show_log(&self) {
         println!("Status: ");
-        let opt = self.executer.receiver.as_ref();
-        match opt {
-            Some(recv) => {
-                let mut logger = self.logger.borrow_mut();
-                logger.add_receiver(Some(recv.clone()));
-                logger.start();
-            },
-            None => {
-                println!("Sync not running");
-            }
-        }
+        logging::resume_console();
     }
 
     fn stop_show_log(&mut self) {
-        let logger = self.logger.borrow();
-        logger.stop();
+        logging::pause_console();
     }
 
     fn list(&self) {
@@ -184,6 +185,34 @@ impl Sync {
             println!("Synched {} rows", row_count);
     }
 
+    fn show_failed_rows(&self) {
+        println!("Failed rows");
+        let print_func = |row: &DeadLetter| {
+            println!("{}.\t{}\t{}\t{}\t{}\t{}\t{}\t{}", row.id, row.op, row.object_name, row.record_id,
+                     row.sfid.as_ref().map(|sfid| sfid.as_str()).unwrap_or(""), row.attempts, row.created, row.error);
+        };
+        let _ = self.setup.list_failed_rows(None, print_func)
+            .map_err(|err| println!("{}", err));
+        println!("r <object> [id] to requeue, d <object> [id] to discard:");
+    }
+
+    fn handle_failed_rows(&self) {
+        let args: Vec<&str> = self.input.split_whitespace().collect();
+        let record_id = args.get(2).and_then(|id| id.parse::<i32>().ok());
+        let result = match (args.get(0), args.get(1)) {
+            (Some(&"r"), Some(object_name)) => self.setup.requeue_failed_rows(object_name, record_id),
+            (Some(&"d"), Some(object_name)) => self.setup.discard_failed_rows(object_name, record_id),
+            _ => {
+                println!("Input invalid");
+                return;
+            }
+        };
+        match result {
+            Ok(count) => println!("Updated {} rows", count),
+            Err(err) => println!("{}", err),
+        }
+    }
+
     fn delete_object(&self) {
         let index = self.input.parse::<isize>().unwrap_or_else(|_err| -1);
         if index == -1 {
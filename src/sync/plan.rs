@@ -0,0 +1,97 @@
+use config::{SyncConfig, ObjectSettings};
+use db::objects::ObjectConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Create,
+    Update,
+    Remove,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PlanItem {
+    pub object: String,
+    pub action: Action,
+    pub changes: Vec<String>,
+}
+
+//compares the declared objects with the synced ones, objects match by case insensitive name
+pub fn plan(config: &SyncConfig, objects: &Vec<ObjectConfig>) -> Vec<PlanItem> {
+    let mut names: Vec<&String> = config.objects.keys().collect();
+    names.sort();
+    let mut items = vec!();
+    for name in names {
+        let settings = &config.objects[name];
+        match objects.iter().find(|obj| obj.name.eq_ignore_ascii_case(name)) {
+            None => items.push(PlanItem {
+                object: name.clone(),
+                action: Action::Create,
+                changes: vec!(),
+            }),
+            Some(object) => {
+                let changes = diff(settings, object);
+                if !changes.is_empty() {
+                    items.push(PlanItem {
+                        object: object.name.clone(),
+                        action: Action::Update,
+                        changes: changes,
+                    });
+                }
+            }
+        }
+    }
+    //a missing or empty objects section is more likely a broken config than a request to drop everything
+    if config.objects.is_empty() {
+        return items;
+    }
+    for object in objects {
+        if !config.objects.keys().any(|name| name.eq_ignore_ascii_case(&object.name)) {
+            items.push(PlanItem {
+                object: object.name.clone(),
+                action: Action::Remove,
+                changes: vec!(),
+            });
+        }
+    }
+    items
+}
+
+//compound mode and db sync are fixed when the object is created and not compared
+pub fn diff(settings: &ObjectSettings, object: &ObjectConfig) -> Vec<String> {
+    let mut changes = vec!();
+    if settings.fields != object.field_selection {
+        changes.push(format!("fields: {:?} -> {:?}", object.field_selection, settings.fields));
+    }
+    if settings.filter != object.filter {
+        changes.push(format!("filter: {:?} -> {:?}", object.filter, settings.filter));
+    }
+    if settings.unmatched != object.unmatched_policy {
+        changes.push(format!("unmatched: {} -> {}", object.unmatched_policy.as_str(), settings.unmatched.as_str()));
+    }
+    if settings.direction != object.direction {
+        changes.push(format!("direction: {} -> {}", object.direction.as_str(), settings.direction.as_str()));
+    }
+    if settings.field_directions != object.field_directions {
+        changes.push(format!("field_directions: {:?} -> {:?}", object.field_directions, settings.field_directions));
+    }
+    if settings.conflict != object.conflict_policy {
+        changes.push(format!("conflict: {} -> {}", object.conflict_policy.as_str(), settings.conflict.as_str()));
+    }
+    if settings.schedule_minutes != object.schedule_minutes {
+        changes.push(format!("schedule_minutes: {} -> {}", object.schedule_minutes, settings.schedule_minutes));
+    }
+    if settings.hard_delete != object.hard_delete {
+        changes.push(format!("hard_delete: {} -> {}", object.hard_delete, settings.hard_delete));
+    }
+    changes
+}
@@ -0,0 +1,18 @@
+use db::Db;
+use db::objects::ObjectConfig;
+use salesforce::Salesforce;
+use sync::selection;
+use logging::Event;
+
+//compares the current describe with the stored field list and alters the table to match
+pub fn reconcile_object(db: &Db, salesforce: &Salesforce, object: &ObjectConfig, drop_removed: bool)
+    -> Result<Vec<String>, String> {
+    let describe = salesforce.describe_object(&object.name).map_err(|err| err.to_string())?;
+    //fields outside the selection are not added and no longer synced
+    let fields = selection::select_fields(&describe.fields, &object.field_selection);
+    let changes = db.alter_object_table(object, &fields, drop_removed)?;
+    for change in &changes {
+        Event::info(&format!("Schema {}", change)).with_object(&object.name).emit();
+    }
+    Ok(changes)
+}
@@ -0,0 +1,132 @@
+use config::FieldSelection;
+use salesforce::objects::Field;
+use salesforce::compound;
+
+//fields the sync cannot work without
+const REQUIRED_FIELDS: [&str; 2] = ["Id", "SystemModstamp"];
+
+//applies the include and exclude lists to a describe field list
+//blob fields are left out, queries only return the url of their content
+pub fn select_fields(fields: &Vec<Field>, selection: &FieldSelection) -> Vec<Field> {
+    let selected: Vec<&String> = fields.iter()
+        .filter(|field| !field.is_blob() && is_selected(selection, &field.name))
+        .map(|field| &field.name)
+        .collect();
+    fields.iter()
+        .filter(|field| {
+            if selected.contains(&&field.name) {
+                return true;
+            }
+            //selecting a compound field selects its components unless they are excluded
+            match field.compound_field_name {
+                Some(ref compound_name) => selected.contains(&compound_name) && !is_excluded(selection, &field.name),
+                None => compound::is_compound(field) && fields.iter().any(|component| {
+                    component.compound_field_name.as_ref() == Some(&field.name) && selected.contains(&&component.name)
+                }),
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+pub fn is_selected(selection: &FieldSelection, name: &str) -> bool {
+    if REQUIRED_FIELDS.contains(&name) {
+        return true;
+    }
+    let included = selection.include.is_empty()
+        || selection.include.iter().any(|pattern| glob_match(pattern, name));
+    included && !is_excluded(selection, name)
+}
+
+fn is_excluded(selection: &FieldSelection, name: &str) -> bool {
+    selection.exclude.iter().any(|pattern| glob_match(pattern, name))
+}
+
+//case insensitive match supporting * and ?, field names are case insensitive in salesforce
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    match_chars(&pattern, &name)
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some(&'*') => (0..name.len() + 1).any(|i| match_chars(&pattern[1..], &name[i..])),
+        Some(&'?') => !name.is_empty() && match_chars(&pattern[1..], &name[1..]),
+        Some(c) => name.first() == Some(c) && match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, sf_type: &str, compound_field_name: Option<&str>) -> Field {
+        Field {
+            name: name.to_owned(),
+            length: 0,
+            label: name.to_owned(),
+            sf_type: sf_type.to_owned(),
+            updateable: true,
+            precision: 0,
+            scale: 0,
+            compound_field_name: compound_field_name.map(|name| name.to_owned()),
+        }
+    }
+
+    fn selection(include: &[&str], exclude: &[&str]) -> FieldSelection {
+        FieldSelection {
+            include: include.iter().map(|pattern| pattern.to_string()).collect(),
+            exclude: exclude.iter().map(|pattern| pattern.to_string()).collect(),
+        }
+    }
+
+    fn names(fields: &[Field]) -> Vec<&str> {
+        fields.iter().map(|field| field.name.as_str()).collect()
+    }
+
+    #[test]
+    fn exclude_beats_include() {
+        let selection = selection(&["Billing*"], &["BillingCity"]);
+        assert!(is_selected(&selection, "BillingStreet"));
+        assert!(!is_selected(&selection, "BillingCity"));
+        assert!(!is_selected(&selection, "Name"));
+    }
+
+    #[test]
+    fn required_fields_cannot_be_excluded() {
+        let selection = selection(&["Name"], &["*"]);
+        assert!(is_selected(&selection, "Id"));
+        assert!(is_selected(&selection, "SystemModstamp"));
+        assert!(!is_selected(&selection, "Name"));
+    }
+
+    #[test]
+    fn matches_patterns_ignoring_case() {
+        let selection = selection(&["billing?ity", "*__C"], &[]);
+        assert!(is_selected(&selection, "BillingCity"));
+        assert!(is_selected(&selection, "Custom__c"));
+        assert!(!is_selected(&selection, "BillingCountry"));
+    }
+
+    #[test]
+    fn selects_components_of_selected_compounds() {
+        let fields = vec!(
+            field("Id", "id", None),
+            field("BillingAddress", "address", None),
+            field("BillingCity", "string", Some("BillingAddress")),
+            field("BillingStreet", "string", Some("BillingAddress")),
+            field("Name", "string", None),
+        );
+        let selected = select_fields(&fields, &selection(&["BillingAddress"], &["BillingStreet"]));
+        assert_eq!(names(&selected), vec!("Id", "BillingAddress", "BillingCity"));
+    }
+
+    #[test]
+    fn leaves_out_blob_fields() {
+        let fields = vec!(field("Id", "id", None), field("Body", "base64", None), field("Name", "string", None));
+        let selected = select_fields(&fields, &selection(&[], &[]));
+        assert_eq!(names(&selected), vec!("Id", "Name"));
+    }
+}
@@ -2,9 +2,15 @@ use db::Db;
 use salesforce::Salesforce;
 use std::sync::Arc;
 use std::cell::RefCell;
-use salesforce::objects::SObject;
+use salesforce::objects::{SObject, SObjectSetup};
 use db::objects::ObjectConfig;
+use db::dead_letter::DeadLetter;
 use std::io::{self, Write};
+use chrono::prelude::*;
+use config::SyncConfig;
+use sync::selection;
+use sync::schema;
+use sync::plan::{self, Action, PlanItem};
 
 const ERR_OBJECT_NOT_FOUND: &str = "Object not found";
 const ERR_CACHE_NOT_SETUP: &str = "Cache not setup";
@@ -19,21 +25,23 @@ pub struct Setup {
     salesforce: Arc<Salesforce>,
     db: Arc<Db>,
     cache: RefCell<SyncObjectCache>,
+    config: &'static SyncConfig,
 }
 
 impl Setup {
-    pub fn new(db: Arc<Db>, salesforce: Arc<Salesforce>) -> Setup {
+    pub fn new(db: Arc<Db>, salesforce: Arc<Salesforce>, config: &'static SyncConfig) -> Setup {
         Setup {
             salesforce: salesforce,
             db: db,
             cache: Default::default(),
+            config: config,
         }
     }
 
     pub fn list_salesforce_objects<F>(&self, print_func: F) -> Result<usize, String>
         where F: FnMut(&(u32, &String, bool)) 
     {
-        let sf_objects = self.salesforce.get_objects()?;
+        let sf_objects = self.salesforce.get_objects().map_err(|err| err.to_string())?;
         self.cache.borrow_mut().sf_objects = Some(sf_objects);
         let mut i:u32 = 0;
         let result = self.cache
@@ -76,42 +84,123 @@ impl Setup {
                            index: usize,
                            setup_db_sync: bool)
                            -> Result<(String, u64), String> {
-        let cache = &self.cache.borrow();
-        let item = &cache
-            .sf_objects
-            .as_ref()
-            .ok_or(ERR_CACHE_NOT_SETUP)?
-            .get(index-1)
-            .ok_or(ERR_OBJECT_NOT_FOUND)?;
-        // println!("selected object: {}", item.name);
-        let describe = self.salesforce.describe_object(&item.name)?;
-        self.db.save_config_data(&describe);
-        self.db.create_object_table(&item.name, &describe.fields);
+        let name = {
+            let cache = &self.cache.borrow();
+            cache
+                .sf_objects
+                .as_ref()
+                .ok_or(ERR_CACHE_NOT_SETUP)?
+                .get(index-1)
+                .ok_or(ERR_OBJECT_NOT_FOUND)?
+                .name
+                .clone()
+        };
+        // println!("selected object: {}", name);
+        self.setup_object(&name, setup_db_sync)
+    }
+
+    pub fn setup_object(&self, object_name: &str, setup_db_sync: bool) -> Result<(String, u64), String> {
+        let settings = self.config.get_object_settings(object_name);
+        let mut describe = self.salesforce.describe_object(object_name).map_err(|err| err.to_string())?;
+        let name = describe.name.clone();
+        if self.db.get_object_config(&name.to_lowercase()).is_ok() {
+            return Err(format!("Object {} is already synced", name));
+        }
+        describe.fields = selection::select_fields(&describe.fields, &settings.fields);
+        let object = SObjectSetup {
+            describe: &describe,
+            settings: &settings,
+            compound_mode: self.config.compound_fields,
+        };
+        //changes made while the records are loaded are pulled by the first sync
+        let started = Utc::now();
+        let result = self.load_object(&object, &name, setup_db_sync)
+            .and_then(|row_count| {
+                self.db.save_config_data(&object, &started.naive_utc())?;
+                Ok(row_count)
+            });
+        if result.is_err() {
+            //without a config row the object is set up again by the next add or apply
+            let _ = self.db.drop_object_table(&name);
+        }
+        result.map(|row_count| (name, row_count))
+    }
+
+    fn load_object(&self, object: &SObjectSetup, name: &String, setup_db_sync: bool) -> Result<u64, String> {
+        self.db.create_object_table(name, &object.describe.fields, object.compound_mode)?;
         if setup_db_sync {
-            self.db.add_channel_trigger(&item.name);
+            self.db.add_channel_trigger(name)?;
+        }
+        if self.salesforce.use_bulk_query(object).map_err(|err| err.to_string())? {
+            self.populate_bulk(object)
+        } else {
+            self.populate_rest(object, name)
         }
+    }
+
+    fn populate_rest(&self, describe: &SObjectSetup, object_name: &str) -> Result<u64, String> {
         let wrapper = self.salesforce
-            .get_records_from_describe(&describe, &item.name)?;
+            .get_records_from_describe(describe, object_name)
+            .map_err(|err| err.to_string())?;
         let mut row_count = 0;
         row_count += self.db.populate(&wrapper)?;
-        print!(".");
-        io::stdout().flush().unwrap();
+        eprint!(".");
+        io::stderr().flush().unwrap();
         // println!("Synched {} rows", row_count);
-        let mut next_wrapper_opt = self.salesforce.get_next_records(&describe, &wrapper);
+        let mut next_wrapper_opt = self.salesforce.get_next_records(describe, &wrapper)
+            .map_err(|err| err.to_string())?;
         while let Some(next_wrapper) = next_wrapper_opt {
             row_count += self.db.populate(&next_wrapper)?;
-            print!(".");
-            io::stdout().flush().unwrap();
+            eprint!(".");
+            io::stderr().flush().unwrap();
             // println!("Synched {} rows", row_count);
             if !next_wrapper.done {
                 // println!("Next Path: {}", next_wrapper.next_url);
             } else {
-                println!("");
+                eprintln!("");
                 // println!("Done: {} rows", row_count);
             }
-            next_wrapper_opt = self.salesforce.get_next_records(&describe, &next_wrapper);
+            next_wrapper_opt = self.salesforce.get_next_records(describe, &next_wrapper)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(row_count)
+    }
+
+    fn populate_bulk(&self, describe: &SObjectSetup) -> Result<u64, String> {
+        let job = self.salesforce.create_bulk_query(describe).map_err(|err| err.to_string())?;
+        eprintln!("Bulk query job {} created", job.id);
+        let job = self.salesforce.wait_for_bulk_query(job).map_err(|err| err.to_string())?;
+        let wrapper = self.salesforce.get_bulk_records(describe, &job, None).map_err(|err| err.to_string())?;
+        let mut row_count = self.db.populate(&wrapper)?;
+        eprint!(".");
+        io::stderr().flush().unwrap();
+        let mut next_wrapper_opt = self.salesforce.get_next_bulk_records(describe, &job, &wrapper)
+            .map_err(|err| err.to_string())?;
+        while let Some(next_wrapper) = next_wrapper_opt {
+            row_count += self.db.populate(&next_wrapper)?;
+            eprint!(".");
+            io::stderr().flush().unwrap();
+            next_wrapper_opt = self.salesforce.get_next_bulk_records(describe, &job, &next_wrapper)
+                .map_err(|err| err.to_string())?;
         }
-        Ok((item.name.clone(), row_count))
+        eprintln!("");
+        Ok(row_count)
+    }
+
+    pub fn list_failed_rows<F>(&self, object_name: Option<&str>, print_func: F) -> Result<usize, String>
+        where F: FnMut(&DeadLetter)
+    {
+        let dead_letters = self.db.list_dead_letters(object_name)?;
+        Ok(dead_letters.into_iter().inspect(print_func).count())
+    }
+
+    //without a record id every failed row of the object is requeued
+    pub fn requeue_failed_rows(&self, object_name: &str, record_id: Option<i32>) -> Result<u64, String> {
+        self.db.requeue_dead_letters(object_name, record_id)
+    }
+
+    pub fn discard_failed_rows(&self, object_name: &str, record_id: Option<i32>) -> Result<u64, String> {
+        self.db.discard_dead_letters(object_name, record_id)
     }
 
     pub fn delete_db_object(&self, index: usize) -> Result<String, String> {
@@ -121,4 +210,59 @@ impl Setup {
         self.db.destroy(obj.id, &obj.name);
         Ok(obj.name.clone())
     }
+
+    pub fn remove_object(&self, object_name: &str) -> Result<String, String> {
+        let obj = self.db.get_object_config(&object_name.to_lowercase())?;
+        self.db.destroy(obj.id, &obj.name);
+        Ok(obj.name)
+    }
+
+    pub fn get_db_objects(&self) -> Result<Vec<ObjectConfig>, String> {
+        self.db.get_selected_objects(-1)
+    }
+
+    pub fn plan_objects(&self) -> Result<Vec<PlanItem>, String> {
+        let objects = self.db.get_selected_objects(-1)?;
+        Ok(plan::plan(self.config, &objects))
+    }
+
+    //applies the plan and returns the items applied, removals are skipped unless allowed
+    pub fn apply_objects(&self, allow_remove: bool) -> Result<Vec<PlanItem>, String> {
+        let mut applied = vec!();
+        for item in self.plan_objects()? {
+            match item.action {
+                Action::Create => {
+                    let settings = self.config.get_object_settings(&item.object);
+                    self.setup_object(&item.object, settings.db_sync)?;
+                },
+                Action::Update => self.update_object(&item.object)?,
+                Action::Remove => {
+                    if !allow_remove {
+                        continue;
+                    }
+                    self.remove_object(&item.object)?;
+                },
+            }
+            applied.push(item);
+        }
+        Ok(applied)
+    }
+
+    fn update_object(&self, object_name: &str) -> Result<(), String> {
+        let object = self.db.get_object_config(&object_name.to_lowercase())?;
+        let settings = self.config.find_object_settings(object_name)
+            .cloned()
+            .ok_or(ERR_OBJECT_NOT_FOUND)?;
+        self.db.update_object_settings(object.id, &settings)?;
+        //a changed selection adds or drops columns
+        if settings.fields != object.field_selection {
+            let object = self.db.get_object_config(&object_name.to_lowercase())?;
+            schema::reconcile_object(&self.db, &self.salesforce, &object, self.config.drop_removed_columns)?;
+        }
+        //new columns and newly matching records are only filled by pulling everything again
+        if settings.fields != object.field_selection || settings.filter != object.filter {
+            self.db.reset_watermark(object.id)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,24 @@
+use libc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+//a second signal exits right away without waiting for the running cycle
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(1) };
+    }
+}
+
+//SIGTERM and SIGINT only set a flag, the sync loop checks it and stops the workers
+pub fn install_handlers() {
+    let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}